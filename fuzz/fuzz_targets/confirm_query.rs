@@ -0,0 +1,10 @@
+#![no_main]
+
+use actix_web::web::Query;
+use libfuzzer_sys::fuzz_target;
+use zero2prod::routes::Parameters;
+
+// The extraction of the query string of `GET /subscriptions/confirm`.
+fuzz_target!(|query: &str| {
+    let _ = Query::<Parameters>::from_query(query);
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zero2prod::{
+    domain::NewSubscriber,
+    routes::FormData,
+};
+
+// What `POST /subscriptions` does with its body before touching the
+// database.
+fuzz_target!(|body: &[u8]| {
+    if let Ok(form) = serde_urlencoded::from_bytes::<FormData>(body) {
+        let _ = NewSubscriber::try_from(form);
+    }
+});
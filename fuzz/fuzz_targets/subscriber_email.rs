@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zero2prod::domain::SubscriberEmail;
+
+fuzz_target!(|email: String| {
+    let Ok(email) = SubscriberEmail::parse(email) else {
+        return;
+    };
+    // Normalized emails are valid and already normalized.
+    let again =
+        SubscriberEmail::parse(email.as_ref().into()).expect("A normalized email was rejected.");
+    assert_eq!(again.as_ref(), email.as_ref());
+    let stripped = email.without_plus_addressing();
+    SubscriberEmail::parse(stripped.as_ref().into())
+        .expect("Stripping plus addressing produced an invalid email.");
+});
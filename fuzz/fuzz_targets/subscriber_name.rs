@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zero2prod::domain::SubscriberName;
+
+fuzz_target!(|name: String| {
+    if let Ok(parsed) = SubscriberName::parse(name.clone()) {
+        // Valid names are stored as they were submitted.
+        assert_eq!(parsed.as_ref(), name);
+    }
+});
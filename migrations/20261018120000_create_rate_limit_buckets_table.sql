@@ -0,0 +1,7 @@
+-- Create Rate Limit Buckets Table
+-- Backs the `postgres` rate limiting backend, shared by every instance.
+CREATE TABLE rate_limit_buckets(
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at timestamptz NOT NULL
+);
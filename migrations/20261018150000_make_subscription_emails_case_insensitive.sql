@@ -0,0 +1,37 @@
+-- Emails are unique regardless of case. Subscribers whose emails only differ
+-- by case are merged first.
+
+-- Each group of duplicates is merged into its oldest subscriber.
+CREATE TEMPORARY TABLE subscription_merges ON COMMIT DROP AS
+SELECT id, first_value(id) OVER (PARTITION BY lower(email) ORDER BY subscribed_at, id) AS survivor_id
+FROM subscriptions;
+
+-- The survivor keeps the most advanced status of its group.
+UPDATE subscriptions AS survivor
+SET status = merged.status
+FROM (
+    SELECT DISTINCT ON (m.survivor_id) m.survivor_id, s.status
+    FROM subscription_merges m
+    JOIN subscriptions s ON s.id = m.id
+    ORDER BY m.survivor_id, CASE s.status WHEN 'confirmed' THEN 1 ELSE 0 END DESC
+) AS merged
+WHERE survivor.id = merged.survivor_id AND survivor.status <> merged.status;
+
+-- Tokens sent to the duplicates remain valid.
+UPDATE subscription_tokens AS t
+SET subscriber_id = m.survivor_id
+FROM subscription_merges m
+WHERE t.subscriber_id = m.id AND m.id <> m.survivor_id;
+
+DELETE FROM subscriptions AS s
+USING subscription_merges m
+WHERE s.id = m.id AND m.id <> m.survivor_id;
+
+-- The application lowercases the domain of new emails from now on. Local
+-- parts may be case-sensitive: they are kept as typed.
+UPDATE subscriptions
+SET email = substring(email FROM '^(.*)@') || '@' || lower(substring(email FROM '@([^@]*)$'))
+WHERE email LIKE '%@%' AND substring(email FROM '@([^@]*)$') <> lower(substring(email FROM '@([^@]*)$'));
+
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+CREATE UNIQUE INDEX subscriptions_lower_email_key ON subscriptions (lower(email));
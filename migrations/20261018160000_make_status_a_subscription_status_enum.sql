@@ -0,0 +1,12 @@
+-- `status` only accepts the values of `domain::SubscriptionStatus`.
+CREATE TYPE subscription_status AS ENUM (
+    'pending_confirmation',
+    'confirmed',
+    'unsubscribed',
+    'bounced',
+    'complained',
+    'suppressed'
+);
+
+ALTER TABLE subscriptions
+    ALTER COLUMN status TYPE subscription_status USING status::subscription_status;
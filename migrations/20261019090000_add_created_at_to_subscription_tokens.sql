@@ -0,0 +1,3 @@
+-- Confirmation links expire: remember when each token was issued. Tokens
+-- issued before this migration count from now.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,58 @@
+//! src/authentication.rs
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString,
+    Algorithm,
+    Argon2,
+    Params,
+    PasswordHasher,
+    Version,
+};
+use secrecy::{
+    ExposeSecret,
+    Secret,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Hash a password into a PHC string, ready to be stored.
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(
+        password
+            .expose_secret()
+            .as_bytes(),
+        &salt,
+    )?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+/// Store a new administrator, returning their id.
+#[tracing::instrument(name = "Create an administrator", skip(password, pool))]
+pub async fn create_admin(
+    pool: &PgPool,
+    username: &str,
+    password: Secret<String>,
+) -> Result<Uuid, anyhow::Error> {
+    let user_id = Uuid::new_v4();
+    // Hashing is CPU-intensive: keep it off the async executor.
+    let password_hash = tokio::task::spawn_blocking(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash the password.")?;
+    sqlx::query!(
+        r#"INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)"#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the new administrator.")?;
+    Ok(user_id)
+}
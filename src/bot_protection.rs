@@ -0,0 +1,402 @@
+//! src/bot_protection.rs
+use std::time::Duration;
+
+use actix_web::HttpRequest;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use reqwest::Client;
+use secrecy::{
+    ExposeSecret,
+    Secret,
+};
+
+use crate::{
+    configuration::BotProtectionSettings,
+    rate_limiting::client_ip,
+};
+
+/// The reasons why a submission was taken for a bot's.
+///
+/// People caught by mistake (e.g. by a slow CAPTCHA provider) can be told to
+/// try again, so the reason is not kept secret. Filling in the honeypot is not
+/// one of them: see `BotProtection::honeypot_filled`.
+#[derive(thiserror::Error, serde::Serialize, utoipa::ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum BotCheckError {
+    #[error("The form was submitted too quickly after being displayed.")]
+    SubmittedTooQuickly,
+    #[error("The CAPTCHA was not solved.")]
+    CaptchaMissing,
+    #[error("The CAPTCHA solution was rejected.")]
+    CaptchaRejected,
+}
+
+impl BotCheckError {
+    /// The `code` tag of the `serde` representation.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::SubmittedTooQuickly => "submitted_too_quickly",
+            Self::CaptchaMissing => "captcha_missing",
+            Self::CaptchaRejected => "captcha_rejected",
+        }
+    }
+}
+
+/// Tells people apart from bots on public forms.
+pub struct BotProtection {
+    minimum_time_to_submit: Option<Duration>,
+    captcha: Option<CaptchaVerifier>,
+    trust_forwarded_headers: bool,
+}
+
+impl BotProtection {
+    pub fn new(
+        settings: &BotProtectionSettings,
+        trust_forwarded_headers: bool,
+    ) -> Result<Self, reqwest::Error> {
+        let captcha = settings
+            .captcha
+            .as_ref()
+            .map(|captcha| {
+                CaptchaVerifier::new(
+                    captcha.verify_url.clone(),
+                    captcha.secret_key.clone(),
+                    captcha.timeout(),
+                )
+            })
+            .transpose()?;
+        Ok(Self {
+            minimum_time_to_submit: settings.minimum_time_to_submit(),
+            captcha,
+            trust_forwarded_headers,
+        })
+    }
+
+    /// Whether `honeypot`, a field people cannot see, was filled in.
+    ///
+    /// Such submissions must look successful: a bot told why it was turned
+    /// away would learn to leave the field empty.
+    pub fn honeypot_filled(&self, honeypot: Option<&str>) -> bool {
+        honeypot.is_some_and(|value| !value.trim().is_empty())
+    }
+
+    /// The check that can be run without calling anyone: the form must have
+    /// been rendered, as a Unix timestamp, long enough before `now`.
+    pub fn check_form(
+        &self,
+        rendered_at: Option<i64>,
+        now: DateTime<Utc>,
+    ) -> Result<(), BotCheckError> {
+        if let Some(minimum) = self.minimum_time_to_submit {
+            // Forms that do not say when they were rendered, or that claim to
+            // be from the future, do not get the benefit of the doubt.
+            let elapsed = rendered_at
+                .and_then(|rendered_at| DateTime::from_timestamp(rendered_at, 0))
+                .and_then(|rendered_at| {
+                    (now - rendered_at)
+                        .to_std()
+                        .ok()
+                });
+            if elapsed.is_none_or(|elapsed| elapsed < minimum) {
+                return Err(BotCheckError::SubmittedTooQuickly);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `response`, as produced by the CAPTCHA widget, with the provider.
+    ///
+    /// Always succeeds if no provider is configured.
+    pub async fn check_captcha(
+        &self,
+        response: Option<&str>,
+        req: &HttpRequest,
+    ) -> Result<Result<(), BotCheckError>, reqwest::Error> {
+        let Some(verifier) = &self.captcha else {
+            return Ok(Ok(()));
+        };
+        let Some(response) = response.filter(|response| !response.is_empty()) else {
+            return Ok(Err(BotCheckError::CaptchaMissing));
+        };
+        let remote_ip = client_ip(req, self.trust_forwarded_headers);
+        if verifier
+            .verify(response, remote_ip.as_deref())
+            .await?
+        {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(BotCheckError::CaptchaRejected))
+        }
+    }
+}
+
+/// A client for the `siteverify` endpoint of hCaptcha, Turnstile, reCAPTCHA
+/// or any provider speaking the same protocol.
+#[derive(Debug)]
+pub struct CaptchaVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret_key: Secret<String>,
+}
+
+impl CaptchaVerifier {
+    pub fn new(
+        verify_url: String,
+        secret_key: Secret<String>,
+        timeout: Duration,
+    ) -> Result<Self, reqwest::Error> {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()?;
+        Ok(Self {
+            http_client,
+            verify_url,
+            secret_key,
+        })
+    }
+
+    /// Whether `response` is a valid solution, according to the provider.
+    #[tracing::instrument(name = "Verify a CAPTCHA solution", skip(self, response))]
+    pub async fn verify(
+        &self,
+        response: &str,
+        remote_ip: Option<&str>,
+    ) -> Result<bool, reqwest::Error> {
+        let request_body = VerifyRequest {
+            secret: self
+                .secret_key
+                .expose_secret(),
+            response,
+            remoteip: remote_ip,
+        };
+        let outcome: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&request_body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if !outcome.success {
+            tracing::info!(
+                error_codes = ?outcome.error_codes,
+                "The CAPTCHA provider rejected a solution."
+            );
+        }
+        Ok(outcome.success)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct VerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remoteip: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::Duration,
+    };
+
+    use chrono::Utc;
+    use claims::{
+        assert_err,
+        assert_err_eq,
+        assert_ok,
+        assert_ok_eq,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{
+            method,
+            path,
+        },
+        Mock,
+        MockServer,
+        Request,
+        ResponseTemplate,
+    };
+
+    use super::{
+        BotCheckError,
+        BotProtection,
+        CaptchaVerifier,
+    };
+
+    struct VerifyBodyMatcher {
+        remote_ip: Option<&'static str>,
+    }
+    impl wiremock::Match for VerifyBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let Ok(body) = serde_urlencoded::from_bytes::<HashMap<String, String>>(&request.body)
+            else {
+                return false;
+            };
+            body.get("secret")
+                .is_some_and(|secret| secret == "secret-key")
+                && body
+                    .get("response")
+                    .is_some_and(|response| response == "solution")
+                && body
+                    .get("remoteip")
+                    .map(String::as_str)
+                    == self.remote_ip
+        }
+    }
+
+    #[test]
+    fn codes_match_the_serde_representation() {
+        for e in [
+            BotCheckError::SubmittedTooQuickly,
+            BotCheckError::CaptchaMissing,
+            BotCheckError::CaptchaRejected,
+        ] {
+            assert_eq!(serde_json::to_value(&e).unwrap()["code"], e.code());
+        }
+    }
+
+    fn verifier(base_url: String) -> CaptchaVerifier {
+        CaptchaVerifier::new(
+            format!("{}/siteverify", base_url),
+            Secret::new("secret-key".into()),
+            Duration::from_millis(200),
+        )
+        .unwrap()
+    }
+
+    fn bot_protection(minimum_time_to_submit: Option<Duration>) -> BotProtection {
+        BotProtection {
+            minimum_time_to_submit,
+            captcha: None,
+            trust_forwarded_headers: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_sends_the_secret_the_solution_and_the_client_ip() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .and(method("POST"))
+            .and(VerifyBodyMatcher {
+                remote_ip: Some("203.0.113.7"),
+            })
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = verifier(mock_server.uri())
+            .verify("solution", Some("203.0.113.7"))
+            .await;
+
+        // Assert
+        assert_ok_eq!(outcome, true);
+    }
+
+    #[tokio::test]
+    async fn verify_returns_false_if_the_provider_rejects_the_solution() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(VerifyBodyMatcher { remote_ip: None })
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = verifier(mock_server.uri())
+            .verify("solution", None)
+            .await;
+
+        // Assert
+        assert_ok_eq!(outcome, false);
+    }
+
+    #[tokio::test]
+    async fn verify_fails_if_the_provider_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = verifier(mock_server.uri())
+            .verify("solution", None)
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn verify_times_out_if_the_provider_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "success": true }))
+                    .set_delay(Duration::from_secs(180)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = verifier(mock_server.uri())
+            .verify("solution", None)
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn only_a_honeypot_with_a_value_counts_as_filled_in() {
+        let bot_protection = bot_protection(None);
+
+        assert!(!bot_protection.honeypot_filled(None));
+        assert!(!bot_protection.honeypot_filled(Some(" ")));
+        assert!(bot_protection.honeypot_filled(Some("https://spam.example")));
+    }
+
+    #[test]
+    fn forms_must_be_rendered_long_enough_before_being_submitted() {
+        let bot_protection = bot_protection(Some(Duration::from_secs(3)));
+        let now = Utc::now();
+        let seconds_ago = |seconds: i64| Some(now.timestamp() - seconds);
+
+        assert_ok!(bot_protection.check_form(seconds_ago(3), now));
+        assert_ok!(bot_protection.check_form(seconds_ago(600), now));
+        for rendered_at in [seconds_ago(1), seconds_ago(-60), None] {
+            assert_err_eq!(
+                bot_protection.check_form(rendered_at, now),
+                BotCheckError::SubmittedTooQuickly
+            );
+        }
+    }
+}
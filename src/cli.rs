@@ -0,0 +1,201 @@
+//! src/cli.rs
+//! The subcommands of the `zero2prod` binary, so that operators can handle
+//! common tasks without a `psql` shell.
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rand::{
+    distributions::Alphanumeric,
+    thread_rng,
+    Rng,
+};
+use secrecy::Secret;
+
+use crate::{
+    authentication::create_admin,
+    configuration::{
+        ConfigurationError,
+        Settings,
+    },
+    domain::{
+        SubscriberEmail,
+        SubscriptionStatus,
+    },
+    email_client::EmailClient,
+    migrations::run_migrations,
+    startup::{
+        get_connection_pool,
+        Application,
+    },
+};
+
+mod subscribers;
+
+#[derive(clap::Parser, Debug)]
+#[command(
+    name = "zero2prod",
+    version,
+    about = "A newsletter subscription service."
+)]
+pub struct Cli {
+    /// A configuration file layered on top of the environment's profile.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// What to do. Defaults to `serve`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Serve the API.
+    Serve,
+    /// Apply the pending database migrations.
+    Migrate,
+    /// Create an administrator account.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+        /// A random password is generated and printed if none is provided.
+        #[arg(long, env = "ZERO2PROD_ADMIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Manage subscribers.
+    Subscribers {
+        #[command(subcommand)]
+        command: SubscribersCommand,
+    },
+    /// Send an email through the configured email provider.
+    SendTestEmail {
+        /// Who to send the email to.
+        recipient: String,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SubscribersCommand {
+    /// Print subscribers, one per line.
+    List {
+        /// Only list subscribers with this status, e.g. `confirmed`.
+        #[arg(long)]
+        status: Option<SubscriptionStatus>,
+    },
+    /// Add subscribers from a CSV file with `email` and `name` columns.
+    Import {
+        path: PathBuf,
+        /// The status given to imported subscribers.
+        #[arg(long, default_value = "confirmed")]
+        status: SubscriptionStatus,
+    },
+    /// Write subscribers as CSV.
+    Export {
+        /// Where to write the CSV. Defaults to stdout.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Only export subscribers with this status, e.g. `confirmed`.
+        #[arg(long)]
+        status: Option<SubscriptionStatus>,
+    },
+    /// Delete a subscriber and their subscription tokens.
+    Delete { email: String },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Check that the configuration can be loaded and is valid.
+    Check,
+    /// Print the effective configuration, with secrets redacted.
+    Dump,
+}
+
+/// Run `command` against the environment described by `configuration`.
+pub async fn run(command: Command, configuration: Settings) -> Result<(), anyhow::Error> {
+    match command {
+        Command::Serve => {
+            let application = Application::build(configuration).await?;
+            application
+                .run_until_stopped()
+                .await?;
+        }
+        Command::Migrate => {
+            let pool = get_connection_pool(&configuration.database);
+            run_migrations(&pool).await?;
+            println!("The database schema is up to date.");
+        }
+        Command::CreateAdmin { username, password } => {
+            let pool = get_connection_pool(&configuration.database);
+            let (password, generated) = match password {
+                Some(password) => (password, false),
+                None => (generate_password(), true),
+            };
+            let user_id = create_admin(&pool, &username, Secret::new(password.clone())).await?;
+            println!("Created administrator `{}` ({}).", username, user_id);
+            if generated {
+                println!("Password: {}", password);
+            }
+        }
+        Command::Subscribers { command } => {
+            let pool = get_connection_pool(&configuration.database);
+            subscribers::run(command, &pool).await?;
+        }
+        Command::SendTestEmail { recipient } => {
+            let recipient = SubscriberEmail::parse(recipient)?;
+            let timeout = configuration
+                .email_client
+                .timeout();
+            let email_client = EmailClient::new(
+                configuration
+                    .email_client
+                    .base_url
+                    .clone(),
+                configuration
+                    .email_client
+                    .sender()
+                    .context("Invalid sender email address.")?,
+                configuration
+                    .email_client
+                    .authorization_token,
+                timeout,
+            );
+            email_client
+                .send_email(
+                    recipient,
+                    "zero2prod test email",
+                    "This is a test email sent by <code>zero2prod send-test-email</code>.",
+                    "This is a test email sent by `zero2prod send-test-email`.",
+                )
+                .await
+                .context("Failed to send the test email.")?;
+            println!("The test email was accepted by the email provider.");
+        }
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => {
+            // `get_configuration` already validates, but `configuration` may
+            // not come from it.
+            configuration
+                .validate()
+                .map_err(ConfigurationError::Invalid)?;
+            println!("The configuration is valid.");
+        }
+        Command::Config {
+            command: ConfigCommand::Dump,
+        } => {
+            println!("{}", serde_json::to_string_pretty(&configuration)?);
+        }
+    }
+    Ok(())
+}
+
+fn generate_password() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
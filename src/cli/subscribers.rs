@@ -0,0 +1,201 @@
+//! src/cli/subscribers.rs
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+};
+
+use anyhow::Context;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::SubscribersCommand;
+use crate::{
+    domain::{
+        NewSubscriber,
+        SubscriptionStatus,
+    },
+    routes::FormData,
+};
+
+struct SubscriberRow {
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub async fn run(command: SubscribersCommand, pool: &PgPool) -> Result<(), anyhow::Error> {
+    match command {
+        SubscribersCommand::List { status } => {
+            let mut stdout = std::io::stdout().lock();
+            for subscriber in fetch_subscribers(pool, status).await? {
+                writeln!(
+                    stdout,
+                    "{}\t{}\t{}\t{}",
+                    subscriber.email,
+                    subscriber.name,
+                    subscriber.status,
+                    subscriber
+                        .subscribed_at
+                        .to_rfc3339()
+                )?;
+            }
+        }
+        SubscribersCommand::Import { path, status } => {
+            let (imported, skipped) = import(pool, &path, status).await?;
+            println!(
+                "Imported {} subscriber(s), skipped {} already known.",
+                imported, skipped
+            );
+        }
+        SubscribersCommand::Export { output, status } => {
+            let subscribers = fetch_subscribers(pool, status).await?;
+            match output {
+                Some(path) => {
+                    let file = File::create(&path)
+                        .with_context(|| format!("Failed to create {}.", path.display()))?;
+                    export(&subscribers, file)?;
+                }
+                None => export(&subscribers, std::io::stdout().lock())?,
+            }
+        }
+        SubscribersCommand::Delete { email } => {
+            if delete(pool, &email).await? {
+                println!("Deleted {}.", email);
+            } else {
+                anyhow::bail!("There is no subscriber with email {}.", email);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"SELECT email, name, status AS "status: SubscriptionStatus", subscribed_at FROM subscriptions
+        WHERE $1::subscription_status IS NULL OR status = $1
+        ORDER BY subscribed_at"#,
+        status as Option<SubscriptionStatus>,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch subscribers.")
+}
+
+fn export(subscribers: &[SubscriberRow], writer: impl Write) -> Result<(), anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["email", "name", "status", "subscribed_at"])?;
+    for subscriber in subscribers {
+        writer.write_record([
+            subscriber.email.as_str(),
+            subscriber.name.as_str(),
+            subscriber.status.as_str(),
+            subscriber
+                .subscribed_at
+                .to_rfc3339()
+                .as_str(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Add every subscriber listed in the CSV file at `path`.
+///
+/// The file is validated as a whole before anything is written: a single
+/// invalid row aborts the import.
+async fn import(
+    pool: &PgPool,
+    path: &Path,
+    status: SubscriptionStatus,
+) -> Result<(u64, u64), anyhow::Error> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open {}.", path.display()))?;
+    let mut subscribers = Vec::new();
+    let mut errors = Vec::new();
+    for (i, record) in reader
+        .deserialize::<FormData>()
+        .enumerate()
+    {
+        // Line 1 holds the headers.
+        let line = i + 2;
+        match record {
+            Ok(record) => match NewSubscriber::try_from(record) {
+                Ok(subscriber) => subscribers.push(subscriber),
+                Err(e) => errors.push(format!("line {}: {}", line, e)),
+            },
+            Err(e) => errors.push(format!("line {}: {}", line, e)),
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "Invalid subscribers, nothing was imported:\n{}",
+            errors.join("\n")
+        );
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let mut imported = 0;
+    for subscriber in &subscribers {
+        imported += sqlx::query!(
+            r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (lower(email)) DO NOTHING"#,
+            Uuid::new_v4(),
+            subscriber.email.as_ref(),
+            subscriber.name.as_ref(),
+            Utc::now(),
+            status as SubscriptionStatus,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to insert a subscriber.")?
+        .rows_affected();
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the import.")?;
+    Ok((imported, subscribers.len() as u64 - imported))
+}
+
+/// Returns `false` if there is no subscriber with that email.
+async fn delete(pool: &PgPool, email: &str) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens
+        WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE lower(email) = lower($1))"#,
+        email,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscription tokens.")?;
+    let deleted = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE lower(email) = lower($1)"#,
+        email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber.")?
+    .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the deletion.")?;
+    Ok(deleted > 0)
+}
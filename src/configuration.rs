@@ -1,31 +1,334 @@
 //! src/configuration.rs
-use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use std::{
+    collections::BTreeMap,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use secrecy::{
+    ExposeSecret,
+    Secret,
+};
+use serde_aux::field_attributes::{
+    deserialize_number_from_string,
+    deserialize_option_number_from_string,
+};
 use sqlx::{
-    postgres::{PgConnectOptions, PgSslMode},
+    postgres::{
+        PgConnectOptions,
+        PgSslMode,
+    },
     ConnectOptions,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{
+    SubscriberEmail,
+    SubscriberEmailError,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigurationError {
+    #[error("{0}")]
+    Environment(String),
+    #[error("Failed to load the configuration.")]
+    Load(#[from] config::ConfigError),
+    #[error("The configuration is invalid:\n{0}")]
+    Invalid(InvalidSettings),
+}
+
+/// Every problem found while validating `Settings`.
+#[derive(Debug)]
+pub struct InvalidSettings(pub Vec<InvalidSetting>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSetting {
+    /// Where the setting lives, e.g. `email_client.sender_email`.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for setting in &self.0 {
+            writeln!(f, "  - {}: {}", setting.path, setting.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects problems instead of stopping at the first one.
+#[derive(Default)]
+struct Validator(Vec<InvalidSetting>);
 
-#[derive(serde::Deserialize, Clone)]
+impl Validator {
+    fn check(&mut self, path: &str, valid: bool, message: impl Into<String>) {
+        if !valid {
+            self.0.push(InvalidSetting {
+                path: path.into(),
+                message: message.into(),
+            });
+        }
+    }
+
+    fn url(&mut self, path: &str, value: &str) {
+        if let Err(e) = reqwest::Url::parse(value) {
+            self.check(
+                path,
+                false,
+                format!("`{}` is not a valid URL ({}).", value, e),
+            );
+        }
+    }
+
+    fn not_empty(&mut self, path: &str, value: &str) {
+        self.check(path, !value.trim().is_empty(), "It must be set.");
+    }
+
+    fn positive(&mut self, path: &str, value: u64) {
+        self.check(path, value > 0, "It must be greater than 0.");
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
     pub application: ApplicationSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    #[serde(default)]
+    pub health_check: HealthCheckSettings,
+    #[serde(default)]
+    pub rate_limiting: RateLimitingSettings,
+    #[serde(default)]
+    pub bot_protection: BotProtectionSettings,
+    #[serde(default)]
+    pub subscriber_email: SubscriberEmailSettings,
+    #[serde(default)]
+    pub confirmation_page: ConfirmationPageSettings,
+}
+
+impl Settings {
+    /// Check the whole tree, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), InvalidSettings> {
+        let mut v = Validator::default();
+
+        let application = &self.application;
+        v.not_empty("application.host", &application.host);
+        v.url("application.base_url", &application.base_url);
+        if let Some(admin_port) = application.admin_port {
+            v.check(
+                "application.admin_port",
+                admin_port == 0 || admin_port != application.port,
+                "It must differ from `application.port`.",
+            );
+        }
+        v.positive(
+            "application.confirmation_link_validity_hours",
+            application.confirmation_link_validity_hours,
+        );
+
+        let database = &self.database;
+        v.not_empty("database.host", &database.host);
+        v.check("database.port", database.port != 0, "It must not be 0.");
+        v.not_empty("database.username", &database.username);
+        v.not_empty(
+            "database.password",
+            database
+                .password
+                .expose_secret(),
+        );
+        v.not_empty("database.database_name", &database.database_name);
+        v.positive(
+            "database.max_connections",
+            database
+                .max_connections
+                .into(),
+        );
+        v.check(
+            "database.min_connections",
+            database.min_connections <= database.max_connections,
+            "It must not exceed `database.max_connections`.",
+        );
+        v.positive(
+            "database.acquire_timeout_milliseconds",
+            database.acquire_timeout_milliseconds,
+        );
+        if let Some(timeout) = database.statement_timeout_milliseconds {
+            v.positive("database.statement_timeout_milliseconds", timeout);
+        }
+
+        let email_client = &self.email_client;
+        v.url("email_client.base_url", &email_client.base_url);
+        if let Err(e) = email_client.sender() {
+            v.check("email_client.sender_email", false, e.to_string());
+        }
+        v.not_empty(
+            "email_client.authorization_token",
+            email_client
+                .authorization_token
+                .expose_secret(),
+        );
+        v.positive(
+            "email_client.timeout_milliseconds",
+            email_client.timeout_milliseconds,
+        );
+
+        for (route, limits) in &self.rate_limiting.routes {
+            let buckets = [
+                ("per_ip", limits.per_ip),
+                ("per_email", limits.per_email),
+                ("per_email_domain", limits.per_email_domain),
+            ];
+            for (name, bucket) in buckets {
+                if let Some(bucket) = bucket {
+                    let path = format!("rate_limiting.routes.{}.{}", route, name);
+                    v.positive(&format!("{}.capacity", path), bucket.capacity.into());
+                    v.check(
+                        &format!("{}.refill_per_minute", path),
+                        bucket.refill_per_minute > 0.0,
+                        "It must be greater than 0.",
+                    );
+                }
+            }
+        }
+
+        let bot_protection = &self.bot_protection;
+        if let Some(seconds) = bot_protection.minimum_seconds_to_submit {
+            v.positive("bot_protection.minimum_seconds_to_submit", seconds);
+        }
+        if let Some(captcha) = &bot_protection.captcha {
+            v.url("bot_protection.captcha.verify_url", &captcha.verify_url);
+            v.not_empty(
+                "bot_protection.captcha.secret_key",
+                captcha
+                    .secret_key
+                    .expose_secret(),
+            );
+            v.positive(
+                "bot_protection.captcha.timeout_milliseconds",
+                captcha.timeout_milliseconds,
+            );
+        }
+
+        v.positive(
+            "subscriber_email.dns_timeout_milliseconds",
+            self.subscriber_email
+                .dns_timeout_milliseconds,
+        );
+
+        let confirmation_page = &self.confirmation_page;
+        v.not_empty(
+            "confirmation_page.brand_name",
+            &confirmation_page.brand_name,
+        );
+        // It ends up in a stylesheet.
+        v.check(
+            "confirmation_page.accent_color",
+            confirmation_page
+                .accent_color
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '#'),
+            "It must be a hexadecimal color (e.g. `#1d4ed8`) or a color name.",
+        );
+        if let Some(logo_url) = &confirmation_page.logo_url {
+            v.url("confirmation_page.logo_url", logo_url);
+        }
+        if let Some(redirect_url) = &confirmation_page.redirect_url {
+            v.url("confirmation_page.redirect_url", redirect_url);
+        }
+
+        v.positive(
+            "health_check.database_timeout_milliseconds",
+            self.health_check
+                .database_timeout_milliseconds,
+        );
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(self.telemetry.directives()) {
+            v.check(
+                "telemetry.level",
+                false,
+                format!(
+                    "`{}` is not a valid filter ({}).",
+                    self.telemetry.directives(),
+                    e
+                ),
+            );
+        }
+        if let Some(file) = &self.telemetry.file {
+            v.check(
+                "telemetry.file.path",
+                file.path
+                    .file_name()
+                    .is_some()
+                    && !file.path.is_dir(),
+                format!("`{}` must end with a file name.", file.path.display()),
+            );
+        }
+        if let Some(otlp) = &self.telemetry.otlp {
+            v.url("telemetry.otlp.endpoint", &otlp.endpoint);
+            v.positive(
+                "telemetry.otlp.timeout_milliseconds",
+                otlp.timeout_milliseconds,
+            );
+        }
+
+        if v.0.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidSettings(v.0))
+        }
+    }
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// If set, operational endpoints (e.g. `/metrics`) are served on this
+    /// port instead of being exposed alongside the public API.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub admin_port: Option<u16>,
+    /// How long in-flight requests are given to complete on shutdown.
+    #[serde(
+        default = "default_shutdown_grace_period_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub shutdown_grace_period_seconds: u64,
+    /// How long the link of a confirmation email can be followed.
+    #[serde(
+        default = "default_confirmation_link_validity_hours",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub confirmation_link_validity_hours: u64,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_grace_period_seconds)
+    }
+
+    pub fn confirmation_link_validity(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_link_validity_hours * 60 * 60)
+    }
+}
+
+fn default_shutdown_grace_period_seconds() -> u64 {
+    30
+}
+
+fn default_confirmation_link_validity_hours() -> u64 {
+    72
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
+    #[serde(deserialize_with = "deserialize_secret", serialize_with = "redact")]
     pub password: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
@@ -33,6 +336,65 @@ pub struct DatabaseSettings {
     pub database_name: String,
     // Determine if we demand the connection to be encrypted or not
     pub require_ssl: bool,
+    /// Apply the migrations embedded in the binary before serving requests.
+    #[serde(default)]
+    pub run_migrations_on_startup: bool,
+    /// Open a connection at startup, failing fast if the database is
+    /// unreachable, instead of on the first request.
+    #[serde(default)]
+    pub connect_eagerly: bool,
+    #[serde(
+        default = "default_max_connections",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub max_connections: u32,
+    /// How many connections the pool keeps open, even when idle.
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    /// How long to wait for a connection from the pool before giving up.
+    #[serde(
+        default = "default_acquire_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub acquire_timeout_milliseconds: u64,
+    /// Close connections that have been idle for longer than this.
+    #[serde(
+        default = "default_idle_timeout_seconds",
+        deserialize_with = "deserialize_option_number_from_string"
+    )]
+    pub idle_timeout_seconds: Option<u64>,
+    /// Close connections that have been open for longer than this.
+    #[serde(
+        default = "default_max_lifetime_seconds",
+        deserialize_with = "deserialize_option_number_from_string"
+    )]
+    pub max_lifetime_seconds: Option<u64>,
+    /// Abort any statement that takes longer than this, server-side.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub statement_timeout_milliseconds: Option<u64>,
+    /// How connections identify themselves, e.g. in `pg_stat_activity`.
+    #[serde(default = "default_application_name")]
+    pub application_name: String,
+}
+
+fn default_max_connections() -> u32 {
+    10
+}
+
+fn default_acquire_timeout_milliseconds() -> u64 {
+    30_000
+}
+
+fn default_idle_timeout_seconds() -> Option<u64> {
+    Some(600)
+}
+
+fn default_max_lifetime_seconds() -> Option<u64> {
+    Some(1800)
+}
+
+fn default_application_name() -> String {
+    "zero2prod".into()
 }
 
 impl DatabaseSettings {
@@ -52,21 +414,42 @@ impl DatabaseSettings {
             .ssl_mode(ssl_mode)
     }
     pub fn with_db(&self) -> PgConnectOptions {
-        self.without_db()
+        let options = self
+            .without_db()
             .database(&self.database_name)
-            .log_statements(tracing_log::log::LevelFilter::Trace)
+            .application_name(&self.application_name)
+            .log_statements(tracing_log::log::LevelFilter::Trace);
+        match self.statement_timeout_milliseconds {
+            Some(timeout) => options.options([("statement_timeout", timeout.to_string())]),
+            None => options,
+        }
+    }
+
+    pub fn acquire_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.acquire_timeout_milliseconds)
+    }
+
+    pub fn idle_timeout(&self) -> Option<std::time::Duration> {
+        self.idle_timeout_seconds
+            .map(std::time::Duration::from_secs)
+    }
+
+    pub fn max_lifetime(&self) -> Option<std::time::Duration> {
+        self.max_lifetime_seconds
+            .map(std::time::Duration::from_secs)
     }
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
+    #[serde(deserialize_with = "deserialize_secret", serialize_with = "redact")]
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
 }
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
@@ -75,17 +458,495 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct HealthCheckSettings {
+    /// How long the readiness probe waits for the database.
+    #[serde(
+        default = "default_database_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub database_timeout_milliseconds: u64,
+    /// Whether the readiness probe checks that the email provider is
+    /// reachable.
+    #[serde(default)]
+    pub check_email_provider: bool,
+    /// How long the outcome of probing the email provider is reused for.
+    #[serde(
+        default = "default_email_provider_cache_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub email_provider_cache_seconds: u64,
+}
+
+impl Default for HealthCheckSettings {
+    fn default() -> Self {
+        Self {
+            database_timeout_milliseconds: default_database_timeout_milliseconds(),
+            check_email_provider: false,
+            email_provider_cache_seconds: default_email_provider_cache_seconds(),
+        }
+    }
+}
+
+impl HealthCheckSettings {
+    pub fn database_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.database_timeout_milliseconds)
+    }
+
+    pub fn email_provider_cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.email_provider_cache_seconds)
+    }
+}
+
+fn default_database_timeout_milliseconds() -> u64 {
+    1000
+}
+
+fn default_email_provider_cache_seconds() -> u64 {
+    30
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+pub struct RateLimitingSettings {
+    #[serde(default)]
+    pub backend: RateLimitingBackend,
+    /// Identify clients by the right-most `X-Forwarded-For` entry, the one
+    /// appended by the proxy in front of us. Only enable it behind exactly
+    /// one proxy that sets it, or clients can pick their own address.
+    #[serde(default)]
+    pub trust_forwarded_headers: bool,
+    /// The limits enforced on each route, keyed by route pattern, e.g.
+    /// `/subscriptions`.
+    #[serde(default)]
+    pub routes: BTreeMap<String, RouteRateLimits>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitingBackend {
+    /// Buckets live in the memory of each instance.
+    #[default]
+    Memory,
+    /// Buckets live in Postgres, so that limits are shared across instances.
+    Postgres,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default, Debug, PartialEq)]
+pub struct RouteRateLimits {
+    /// Keyed by client IP address.
+    pub per_ip: Option<TokenBucketSettings>,
+    /// Keyed by the `email` field of the submitted form.
+    pub per_email: Option<TokenBucketSettings>,
+    /// Keyed by the domain of the `email` field of the submitted form.
+    pub per_email_domain: Option<TokenBucketSettings>,
+}
+
+/// A bucket holds up to `capacity` requests and regains `refill_per_minute`
+/// of them every minute.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct TokenBucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_per_minute: f64,
+}
+
+/// How the subscribe form tells people apart from bots. Every check is off
+/// by default, except for the honeypot field which costs nothing.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+pub struct BotProtectionSettings {
+    /// Reject forms submitted less than this many seconds after they were
+    /// rendered, or that do not say when they were rendered.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub minimum_seconds_to_submit: Option<u64>,
+    /// Require a solved CAPTCHA.
+    #[serde(default)]
+    pub captcha: Option<CaptchaSettings>,
+}
+
+impl BotProtectionSettings {
+    pub fn minimum_time_to_submit(&self) -> Option<std::time::Duration> {
+        self.minimum_seconds_to_submit
+            .map(std::time::Duration::from_secs)
+    }
+}
+
+/// Any provider speaking the `siteverify` protocol shared by hCaptcha,
+/// Turnstile and reCAPTCHA.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct CaptchaSettings {
+    /// e.g. `https://api.hcaptcha.com/siteverify` or
+    /// `https://challenges.cloudflare.com/turnstile/v0/siteverify`.
+    pub verify_url: String,
+    #[serde(deserialize_with = "deserialize_secret", serialize_with = "redact")]
+    pub secret_key: Secret<String>,
+    #[serde(
+        default = "default_captcha_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub timeout_milliseconds: u64,
+}
+
+impl CaptchaSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+fn default_captcha_timeout_milliseconds() -> u64 {
+    5000
+}
+
+/// The checks applied to the email of would-be subscribers on top of it
+/// being well-formed. They are all off by default.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SubscriberEmailSettings {
+    /// Store `ursula+news@example.com` as `ursula@example.com`.
+    #[serde(default)]
+    pub strip_plus_addressing: bool,
+    /// Reject domains on the bundled list of disposable email providers.
+    #[serde(default)]
+    pub reject_disposable_domains: bool,
+    /// Reject domains without MX (or A/AAAA) records.
+    #[serde(default)]
+    pub check_mx_records: bool,
+    #[serde(
+        default = "default_dns_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub dns_timeout_milliseconds: u64,
+}
+
+impl Default for SubscriberEmailSettings {
+    fn default() -> Self {
+        Self {
+            strip_plus_addressing: false,
+            reject_disposable_domains: false,
+            check_mx_records: false,
+            dns_timeout_milliseconds: default_dns_timeout_milliseconds(),
+        }
+    }
+}
+
+impl SubscriberEmailSettings {
+    pub fn dns_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.dns_timeout_milliseconds)
+    }
+}
+
+fn default_dns_timeout_milliseconds() -> u64 {
+    2000
+}
+
+/// What people see after following the link of their confirmation email.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ConfirmationPageSettings {
+    /// Shown in the title and the header of every page.
+    #[serde(default = "default_brand_name")]
+    pub brand_name: String,
+    /// An image shown in the header, if set.
+    #[serde(default)]
+    pub logo_url: Option<String>,
+    /// A CSS color for the header, e.g. `#1d4ed8`.
+    #[serde(default = "default_accent_color")]
+    pub accent_color: String,
+    /// Send browsers there instead of rendering a page, with the outcome in
+    /// the `status` query parameter.
+    #[serde(default)]
+    pub redirect_url: Option<String>,
+    #[serde(default = "default_confirmed_message")]
+    pub confirmed: PageMessage,
+    #[serde(default = "default_already_confirmed_message")]
+    pub already_confirmed: PageMessage,
+    /// Shown for unknown tokens, and for subscriptions that can no longer be
+    /// confirmed with their original link.
+    #[serde(default = "default_invalid_token_message")]
+    pub invalid_token: PageMessage,
+    /// Shown for links followed after `application.confirmation_link_validity_hours`.
+    #[serde(default = "default_expired_token_message")]
+    pub expired_token: PageMessage,
+    #[serde(default = "default_error_message")]
+    pub error: PageMessage,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PageMessage {
+    pub title: String,
+    pub message: String,
+}
+
+impl Default for ConfirmationPageSettings {
+    fn default() -> Self {
+        Self {
+            brand_name: default_brand_name(),
+            logo_url: None,
+            accent_color: default_accent_color(),
+            redirect_url: None,
+            confirmed: default_confirmed_message(),
+            already_confirmed: default_already_confirmed_message(),
+            invalid_token: default_invalid_token_message(),
+            expired_token: default_expired_token_message(),
+            error: default_error_message(),
+        }
+    }
+}
+
+fn default_brand_name() -> String {
+    "Our newsletter".into()
+}
+
+fn default_accent_color() -> String {
+    "#1d4ed8".into()
+}
+
+fn default_confirmed_message() -> PageMessage {
+    PageMessage {
+        title: "You are subscribed!".into(),
+        message: "Thank you for confirming your subscription. Our next issue will land in your \
+                  inbox."
+            .into(),
+    }
+}
+
+fn default_already_confirmed_message() -> PageMessage {
+    PageMessage {
+        title: "You are already subscribed".into(),
+        message: "Your subscription was confirmed before: there is nothing else to do.".into(),
+    }
+}
+
+fn default_invalid_token_message() -> PageMessage {
+    PageMessage {
+        title: "This link is no longer valid".into(),
+        message: "Please subscribe again to receive a new confirmation link.".into(),
+    }
+}
+
+fn default_expired_token_message() -> PageMessage {
+    PageMessage {
+        title: "This link has expired".into(),
+        message: "Confirmation links can only be followed for a few days after they are sent."
+            .into(),
+    }
+}
+
+fn default_error_message() -> PageMessage {
+    PageMessage {
+        title: "Something went wrong".into(),
+        message: "We could not confirm your subscription. Please try again in a few minutes."
+            .into(),
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct TelemetrySettings {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// The default verbosity, e.g. `info`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Per-module verbosity overrides, e.g. `sqlx: warn`.
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+    /// Also write logs to a file, if set.
+    pub file: Option<LogFileSettings>,
+    /// Export spans to an OpenTelemetry collector, if set.
+    pub otlp: Option<OtlpSettings>,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            level: default_log_level(),
+            filters: BTreeMap::new(),
+            file: None,
+            otlp: None,
+        }
+    }
+}
+
+impl TelemetrySettings {
+    /// The filter directives matching `level` and `filters`, in the syntax
+    /// understood by `RUST_LOG`.
+    pub fn directives(&self) -> String {
+        std::iter::once(self.level.clone())
+            .chain(
+                self.filters
+                    .iter()
+                    .map(|(module, level)| format!("{}={}", module, level)),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn default_log_level() -> String {
+    "info".into()
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Bunyan-compatible JSON, our historical format.
+    #[default]
+    Bunyan,
+    /// `tracing-subscriber`'s own JSON format.
+    Json,
+    /// Multi-line, human-readable output for local development.
+    Pretty,
+    /// Single-line, human-readable output.
+    Compact,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct LogFileSettings {
+    /// Where to write logs. Rotated files get a date suffix.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub rotation: LogRotation,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct OtlpSettings {
+    /// The OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    pub endpoint: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+impl OtlpSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// The settings holding secrets, by path.
+const SECRETS: [&str; 3] = [
+    "database.password",
+    "email_client.authorization_token",
+    "bot_protection.captcha.secret_key",
+];
+
+/// Secrets can be provided inline or, to keep them out of configuration
+/// files, as an indirection: `file:/run/secrets/db_password` reads a file
+/// (e.g. a Docker or Kubernetes secret) and `env:DB_PASSWORD` reads an
+/// environment variable.
+fn deserialize_secret<'de, D>(deserializer: D) -> Result<Secret<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+    resolve_secret(&value)
+        .map(Secret::new)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Secrets never leave the process, even when dumping the configuration.
+fn redact<S>(_secret: &Secret<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str("[REDACTED]")
+}
+
+fn resolve_secret(value: &str) -> Result<String, String> {
+    if let Some(path) = value.strip_prefix("file:") {
+        std::fs::read_to_string(path)
+            // Editors and `echo` add a trailing newline.
+            .map(|secret| {
+                secret
+                    .trim_end_matches(['\n', '\r'])
+                    .to_string()
+            })
+            .map_err(|e| format!("Failed to read the secret in `{}`: {}", path, e))
+    } else if let Some(variable) = value.strip_prefix("env:") {
+        std::env::var(variable)
+            .map_err(|_| format!("The environment variable `{}` is not set.", variable))
+    } else {
+        Ok(value.into())
+    }
+}
+
+fn is_secret_indirection(value: &str) -> bool {
+    value.starts_with("file:") || value.starts_with("env:")
+}
+
+/// The secrets whose value is spelled out in one of the committed files in
+/// `configuration_directory`, i.e. any file but the local override.
+fn committed_secrets(
+    config: &config::Config,
+    configuration_directory: &Path,
+) -> Vec<InvalidSetting> {
+    SECRETS
+        .iter()
+        .filter_map(|path| {
+            let (table, key) = path.rsplit_once('.')?;
+            let value = config
+                .get_table(table)
+                .ok()?
+                .remove(key)?;
+            let origin = value.origin()?.to_owned();
+            // File origins are relative to the current directory, if possible.
+            let committed = std::env::current_dir()
+                .and_then(|current_dir| {
+                    current_dir
+                        .join(&origin)
+                        .canonicalize()
+                })
+                .is_ok_and(|origin| {
+                    configuration_directory
+                        .canonicalize()
+                        .is_ok_and(|directory| origin.starts_with(directory))
+                        && !origin.ends_with(LOCAL_OVERRIDE_FILE)
+                });
+            let inline = value
+                .into_string()
+                .map(|v| !is_secret_indirection(&v))
+                .unwrap_or(true);
+            (committed && inline).then(|| InvalidSetting {
+                path: path.to_string(),
+                message: format!(
+                    "It is spelled out in `{}`. Use a `file:` or `env:` indirection, or an \
+                     `APP_` environment variable, to provide secrets in production.",
+                    origin
+                ),
+            })
+        })
+        .collect()
+}
+
 /// The possible runtime environment for our application.
+///
+/// Each environment is a profile, `configuration/{name}.yaml`, layered on top
+/// of `configuration/base.yaml`.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Environment {
     Local,
+    Staging,
+    Test,
     Production,
+    /// Any other profile, e.g. `demo` for `configuration/demo.yaml`.
+    Named(String),
 }
 
 impl Environment {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             Environment::Local => "local",
+            Environment::Staging => "staging",
+            Environment::Test => "test",
             Environment::Production => "production",
+            Environment::Named(name) => name,
         }
     }
 }
@@ -95,17 +956,44 @@ impl TryFrom<String> for Environment {
     fn try_from(s: String) -> Result<Self, Self::Error> {
         match s.to_lowercase().as_str() {
             "local" => Ok(Self::Local),
+            "staging" => Ok(Self::Staging),
+            "test" => Ok(Self::Test),
             "production" => Ok(Self::Production),
+            other
+                if !other.is_empty()
+                    && other
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Ok(Self::Named(other.into()))
+            }
             other => Err(format!(
-                "{} is not a supported environment. \
-             Use either `local` or `production`.",
+                "`{}` is not a valid environment name. Use `local`, `staging`, `test`, \
+                 `production` or the name of a profile in `configuration/`.",
                 other
             )),
         }
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+/// An optional, git-ignored profile layered on top of the environment's, to
+/// tweak the configuration of a development machine.
+const LOCAL_OVERRIDE_FILE: &str = "local.override.yaml";
+
+/// Load the configuration for the current environment, then validate it.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    get_configuration_from(None)
+}
+
+/// Load the configuration for the current environment, then validate it.
+///
+/// Sources are layered in this order, each overriding the previous ones:
+/// 1. `configuration/base.yaml`;
+/// 2. `configuration/{APP_ENVIRONMENT}.yaml` (`local` by default);
+/// 3. `configuration/local.override.yaml`, if it exists;
+/// 4. `config_file`, if set;
+/// 5. `APP_`-prefixed environment variables.
+pub fn get_configuration_from(config_file: Option<&Path>) -> Result<Settings, ConfigurationError> {
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
     let configuration_directory = base_path.join("configuration");
     // Detect the running environment.
@@ -113,15 +1001,27 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
-    let environment_filename = format!("{}.yaml", environment.as_str());
-    let settings = config::Config::builder()
+        .map_err(ConfigurationError::Environment)?;
+    let environment_file = configuration_directory.join(format!("{}.yaml", environment.as_str()));
+    if !environment_file.exists() {
+        return Err(ConfigurationError::Environment(format!(
+            "There is no profile for the `{}` environment: `{}` does not exist.",
+            environment.as_str(),
+            environment_file.display()
+        )));
+    }
+    let mut builder = config::Config::builder()
         .add_source(config::File::from(
             configuration_directory.join("base.yaml"),
         ))
-        .add_source(config::File::from(
-            configuration_directory.join(environment_filename),
-        ))
+        .add_source(config::File::from(environment_file))
+        .add_source(
+            config::File::from(configuration_directory.join(LOCAL_OVERRIDE_FILE)).required(false),
+        );
+    if let Some(config_file) = config_file {
+        builder = builder.add_source(config::File::from(config_file));
+    }
+    let settings = builder
         // Add in settings from environment variables (with a prefix of APP and
         // '__' as separator)
         // E.g. `APP_APPLICATION__PORT=5001 would set `Settings.application.port`
@@ -131,5 +1031,208 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .separator("__"),
         )
         .build()?;
-    settings.try_deserialize::<Settings>()
+    let mut invalid = match environment {
+        Environment::Production => committed_secrets(&settings, &configuration_directory),
+        _ => Vec::new(),
+    };
+    let settings = settings.try_deserialize::<Settings>()?;
+    if let Err(InvalidSettings(problems)) = settings.validate() {
+        invalid.extend(problems);
+    }
+    if !invalid.is_empty() {
+        return Err(ConfigurationError::Invalid(InvalidSettings(invalid)));
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{
+        assert_err,
+        assert_ok,
+    };
+    use secrecy::Secret;
+
+    use super::{
+        committed_secrets,
+        resolve_secret,
+        CaptchaSettings,
+        Environment,
+        LogFileSettings,
+        LogRotation,
+        Settings,
+        TelemetrySettings,
+    };
+
+    fn settings() -> Settings {
+        let yaml = r#"
+application:
+  port: 8000
+  host: 127.0.0.1
+  base_url: "http://127.0.0.1"
+database:
+  host: localhost
+  port: 5432
+  username: postgres
+  password: password
+  database_name: newsletter
+  require_ssl: false
+email_client:
+  base_url: "http://localhost"
+  sender_email: "test@gmail.com"
+  authorization_token: "my-secret-token"
+  timeout_milliseconds: 10000
+"#;
+        config::Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn valid_settings_are_accepted() {
+        assert_ok!(settings().validate());
+    }
+
+    #[test]
+    fn every_invalid_setting_is_reported_with_its_path() {
+        let mut settings = settings();
+        settings.application.base_url = "127.0.0.1".into();
+        settings
+            .application
+            .admin_port = Some(8000);
+        settings
+            .email_client
+            .sender_email = "not-an-email".into();
+        settings
+            .email_client
+            .authorization_token = Secret::new("".into());
+        settings
+            .email_client
+            .timeout_milliseconds = 0;
+        settings
+            .bot_protection
+            .captcha = Some(CaptchaSettings {
+            verify_url: "siteverify".into(),
+            secret_key: Secret::new("secret".into()),
+            timeout_milliseconds: 5000,
+        });
+        settings
+            .confirmation_page
+            .accent_color = "red; background: url(evil)".into();
+        settings.telemetry.file = Some(LogFileSettings {
+            path: "logs/..".into(),
+            rotation: LogRotation::Never,
+        });
+
+        let invalid = settings
+            .validate()
+            .unwrap_err();
+
+        let paths: Vec<_> = invalid
+            .0
+            .iter()
+            .map(|s| s.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "application.base_url",
+                "application.admin_port",
+                "email_client.sender_email",
+                "email_client.authorization_token",
+                "email_client.timeout_milliseconds",
+                "bot_protection.captcha.verify_url",
+                "confirmation_page.accent_color",
+                "telemetry.file.path",
+            ]
+        );
+    }
+
+    #[test]
+    fn log_directives_combine_the_level_and_the_per_module_filters() {
+        let settings = TelemetrySettings {
+            level: "debug".into(),
+            filters: [
+                ("sqlx".to_string(), "warn".to_string()),
+                ("actix_server".to_string(), "error".to_string()),
+            ]
+            .into(),
+            ..Default::default()
+        };
+
+        assert_eq!(settings.directives(), "debug,actix_server=error,sqlx=warn");
+    }
+
+    #[test]
+    fn secrets_can_be_read_from_files_and_environment_variables() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, "from-a-file\n").unwrap();
+        std::env::set_var("ZERO2PROD_TEST_SECRET", "from-the-environment");
+
+        assert_eq!(
+            resolve_secret(&format!("file:{}", path.display())).unwrap(),
+            "from-a-file"
+        );
+        assert_eq!(
+            resolve_secret("env:ZERO2PROD_TEST_SECRET").unwrap(),
+            "from-the-environment"
+        );
+        assert_eq!(resolve_secret("inline").unwrap(), "inline");
+        assert_err!(resolve_secret("env:ZERO2PROD_TEST_MISSING_SECRET"));
+        assert_err!(resolve_secret("file:/does/not/exist"));
+    }
+
+    #[test]
+    fn secrets_spelled_out_in_committed_files_are_reported() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("production.yaml");
+        std::fs::write(
+            &file,
+            "database:\n  password: hunter2\nemail_client:\n  authorization_token: \"env:TOKEN\"\n",
+        )
+        .unwrap();
+        let config = config::Config::builder()
+            .add_source(config::File::from(file))
+            .build()
+            .unwrap();
+
+        let committed = committed_secrets(&config, &directory);
+
+        let paths: Vec<_> = committed
+            .iter()
+            .map(|s| s.path.as_str())
+            .collect();
+        assert_eq!(paths, ["database.password"]);
+    }
+
+    #[test]
+    fn environments_other_than_the_known_ones_are_named_profiles() {
+        assert_eq!(
+            Environment::try_from("Staging".to_string()),
+            Ok(Environment::Staging)
+        );
+        assert_eq!(
+            Environment::try_from("test".to_string()),
+            Ok(Environment::Test)
+        );
+        assert_eq!(
+            Environment::try_from("demo-eu".to_string()),
+            Ok(Environment::Named("demo-eu".into()))
+        );
+        assert_err!(Environment::try_from("../secrets".to_string()));
+        assert_err!(Environment::try_from("".to_string()));
+    }
+
+    #[test]
+    fn dumped_configuration_does_not_contain_secrets() {
+        let dump = serde_json::to_string(&settings()).unwrap();
+
+        assert!(!dump.contains("my-secret-token"));
+        assert!(dump.contains("[REDACTED]"));
+        assert!(dump.contains("test@gmail.com"));
+    }
 }
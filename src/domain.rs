@@ -1,6 +1,21 @@
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
-pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_name::SubscriberName;
+mod subscription_status;
+pub use new_subscriber::{
+    NewSubscriber,
+    NewSubscriberError,
+};
+pub use subscriber_email::{
+    SubscriberEmail,
+    SubscriberEmailError,
+};
+pub use subscriber_name::{
+    SubscriberName,
+    SubscriberNameError,
+};
+pub use subscription_status::{
+    InvalidStatusTransition,
+    SubscriptionStatus,
+    UnknownSubscriptionStatus,
+};
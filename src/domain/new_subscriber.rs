@@ -1,6 +1,22 @@
-use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::domain::{
+    SubscriberEmail,
+    SubscriberEmailError,
+    SubscriberName,
+    SubscriberNameError,
+};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
 }
+
+/// The reasons why the input provided by a would-be subscriber was rejected,
+/// tagged with the offending field.
+#[derive(thiserror::Error, serde::Serialize, utoipa::ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "field", content = "error", rename_all = "snake_case")]
+pub enum NewSubscriberError {
+    #[error(transparent)]
+    Name(#[from] SubscriberNameError),
+    #[error(transparent)]
+    Email(#[from] SubscriberEmailError),
+}
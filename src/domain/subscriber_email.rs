@@ -1,18 +1,103 @@
 //! src/domain/subscriber_email.rs
 
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
 use validator::ValidateEmail;
 
 #[derive(Debug)]
 
 pub struct SubscriberEmail(String);
+
+/// The reasons why a string is not a valid `SubscriberEmail`.
+///
+/// See `SubscriberNameError` for how the `serde` representation is meant to
+/// be consumed by clients.
+#[derive(thiserror::Error, serde::Serialize, utoipa::ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum SubscriberEmailError {
+    #[error("A subscriber email cannot be empty.")]
+    Empty,
+    #[error("`{email}` is missing the `@` symbol.")]
+    MissingAtSymbol { email: String },
+    #[error("`{email}` is not a valid subscriber email.")]
+    Invalid { email: String },
+    #[error("`{domain}` hands out disposable email addresses.")]
+    DisposableDomain { domain: String },
+    #[error("`{domain}` cannot receive emails.")]
+    UndeliverableDomain { domain: String },
+}
+
+static DISPOSABLE_DOMAINS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    include_str!("disposable_email_domains.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if s.validate_email() {
-            Ok(Self(s))
+    /// Parse and normalize an email address, so that the different spellings
+    /// of an address end up as the same subscriber: its domain is lowercased
+    /// and converted to its ASCII (punycode) form.
+    ///
+    /// The local part is kept as typed: it is case-sensitive (RFC 5321), and
+    /// rewriting it could send our emails to another mailbox.
+    pub fn parse(s: String) -> Result<SubscriberEmail, SubscriberEmailError> {
+        if s.trim().is_empty() {
+            Err(SubscriberEmailError::Empty)
+        } else if !s.contains('@') {
+            Err(SubscriberEmailError::MissingAtSymbol { email: s })
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            match normalize(s.trim()) {
+                Some(email) if email.validate_email() => Ok(Self(email)),
+                _ => Err(SubscriberEmailError::Invalid { email: s }),
+            }
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default()
+    }
+
+    /// Drop the `+tag` suffix of the local part: `ursula+news@example.com`
+    /// becomes `ursula@example.com`.
+    pub fn without_plus_addressing(self) -> Self {
+        match self.0.split_once('@') {
+            Some((local_part, domain)) => match local_part.split_once('+') {
+                Some((local_part, _)) if !local_part.is_empty() => {
+                    Self(format!("{}@{}", local_part, domain))
+                }
+                _ => self,
+            },
+            None => self,
         }
     }
+
+    /// Whether the domain, or one of its parents, is on our bundled list of
+    /// disposable email providers.
+    pub fn is_disposable(&self) -> bool {
+        let mut domain = self.domain();
+        loop {
+            if DISPOSABLE_DOMAINS.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+fn normalize(email: &str) -> Option<String> {
+    let (local_part, domain) = email.rsplit_once('@')?;
+    // Lowercases the domain too.
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    Some(format!("{}@{}", local_part, domain))
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -23,12 +108,21 @@ impl AsRef<str> for SubscriberEmail {
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberEmail;
-    use claims::assert_err;
-    use fake::{faker::internet::en::SafeEmail, Fake};
+    use claims::assert_err_eq;
+    use fake::{
+        faker::internet::en::SafeEmail,
+        Fake,
+    };
     use quickcheck_macros::quickcheck;
-    use rand::rngs::StdRng;
-    use rand::SeedableRng;
+    use rand::{
+        rngs::StdRng,
+        SeedableRng,
+    };
+
+    use super::{
+        SubscriberEmail,
+        SubscriberEmailError,
+    };
 
     // Both `Clone` and `Debug` are required by `quickcheck`
     #[derive(Debug, Clone)]
@@ -43,25 +137,154 @@ mod tests {
         }
     }
 
+    /// Emails as people type them: mixed case, plus addressing and
+    /// internationalized domains.
+    #[derive(Debug, Clone)]
+    struct TypedEmailFixture(String);
+
+    impl quickcheck::Arbitrary for TypedEmailFixture {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            const LOCAL_PART: &[char] = &['a', 'Z', 'u', 'R', '0', '9', '.', '_', '-', '+', '%'];
+            const LABELS: &[&str] = &[
+                "example",
+                "Example",
+                "mail-1",
+                "bücher",
+                "MÜNCHEN",
+                "пример",
+                "例え",
+            ];
+            const TLDS: &[&str] = &["com", "ORG", "io", "рф"];
+            let mut local_part = vec![*g
+                .choose(&LOCAL_PART[..6])
+                .unwrap()];
+            local_part
+                .extend((0..usize::arbitrary(g) % 20).map(|_| *g.choose(LOCAL_PART).unwrap()));
+            let mut labels: Vec<&str> = (0..1 + usize::arbitrary(g) % 3)
+                .map(|_| *g.choose(LABELS).unwrap())
+                .collect();
+            labels.push(g.choose(TLDS).unwrap());
+            Self(format!(
+                "{}@{}",
+                local_part
+                    .into_iter()
+                    .collect::<String>(),
+                labels.join(".")
+            ))
+        }
+    }
+
     #[test]
     fn empty_string_is_rejected() {
         let email = "".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_err_eq!(SubscriberEmail::parse(email), SubscriberEmailError::Empty);
     }
     #[test]
     fn email_missing_at_symbol_is_rejected() {
         let email = "ursuladomain.com".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_err_eq!(
+            SubscriberEmail::parse(email.clone()),
+            SubscriberEmailError::MissingAtSymbol { email }
+        );
     }
 
     #[test]
     fn email_missing_subject_is_rejected() {
         let email = "@domain.com".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_err_eq!(
+            SubscriberEmail::parse(email.clone()),
+            SubscriberEmailError::Invalid { email }
+        );
+    }
+
+    #[test]
+    fn emails_are_normalized() {
+        let cases = [
+            ("Foo@Example.COM", "Foo@example.com"),
+            (" ursula@example.com ", "ursula@example.com"),
+            ("ursula@bücher.example", "ursula@xn--bcher-kva.example"),
+            ("ursula@BÜCHER.example", "ursula@xn--bcher-kva.example"),
+        ];
+        for (email, expected) in cases {
+            let email = SubscriberEmail::parse(email.to_string()).unwrap();
+            assert_eq!(email.as_ref(), expected);
+        }
+    }
+
+    #[test]
+    fn plus_addressing_can_be_stripped() {
+        let cases = [
+            ("ursula+news@example.com", "ursula@example.com"),
+            ("ursula+a+b@example.com", "ursula@example.com"),
+            ("ursula@example.com", "ursula@example.com"),
+            ("+news@example.com", "+news@example.com"),
+        ];
+        for (email, expected) in cases {
+            let email = SubscriberEmail::parse(email.to_string())
+                .unwrap()
+                .without_plus_addressing();
+            assert_eq!(email.as_ref(), expected);
+        }
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_recognized() {
+        let disposable = |email: &str| {
+            SubscriberEmail::parse(email.to_string())
+                .unwrap()
+                .is_disposable()
+        };
+
+        assert!(disposable("ursula@mailinator.com"));
+        assert!(disposable("ursula@eu.mailinator.com"));
+        assert!(!disposable("ursula@gmail.com"));
+        assert!(!disposable("ursula@notmailinator.com"));
     }
 
     #[quickcheck]
     fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
     }
+
+    #[quickcheck]
+    fn typed_emails_get_a_lowercase_ascii_domain_and_keep_their_local_part(
+        email: TypedEmailFixture,
+    ) -> bool {
+        let (local_part, _) = email
+            .0
+            .rsplit_once('@')
+            .unwrap();
+        let local_part = local_part.to_string();
+        SubscriberEmail::parse(email.0).is_ok_and(|email| {
+            let domain = email.domain();
+            domain.is_ascii()
+                && domain == domain.to_ascii_lowercase()
+                && email
+                    .as_ref()
+                    .strip_suffix(domain)
+                    .and_then(|rest| rest.strip_suffix('@'))
+                    == Some(local_part.as_str())
+        })
+    }
+
+    #[quickcheck]
+    fn normalizing_is_idempotent(email: TypedEmailFixture) -> bool {
+        let email = SubscriberEmail::parse(email.0).unwrap();
+        SubscriberEmail::parse(email.as_ref().into())
+            .is_ok_and(|again| again.as_ref() == email.as_ref())
+    }
+
+    #[quickcheck]
+    fn stripping_plus_addressing_keeps_the_domain(email: TypedEmailFixture) -> bool {
+        let email = SubscriberEmail::parse(email.0).unwrap();
+        let domain = email.domain().to_string();
+        let stripped = email.without_plus_addressing();
+        stripped.domain() == domain && SubscriberEmail::parse(stripped.as_ref().into()).is_ok()
+    }
+
+    #[quickcheck]
+    fn parsing_never_panics(email: String) -> bool {
+        let _ = SubscriberEmail::parse(email);
+        true
+    }
 }
@@ -1,14 +1,38 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+/// The maximum length of a subscriber name, in graphemes.
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
+
+/// The reasons why a string is not a valid `SubscriberName`.
+///
+/// The `serde` representation is part of our API: the `code` tag is stable
+/// and, together with the details carried by each variant, lets clients
+/// render a localized message instead of relying on our English `Display`.
+#[derive(thiserror::Error, serde::Serialize, utoipa::ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum SubscriberNameError {
+    #[error("A subscriber name cannot be empty or whitespace-only.")]
+    Empty,
+    #[error("A subscriber name cannot be longer than {max} graphemes, got {graphemes}.")]
+    TooLong { graphemes: usize, max: usize },
+    #[error("A subscriber name cannot contain the character `{character}`.")]
+    ForbiddenCharacter { character: char },
+}
+
 impl SubscriberName {
-    /// Returns an instance of `SubscriberName` if the input satisfies all /// our validation
-    /// constraints on subscriber names. It panics otherwise.
-    pub fn parse(s: String) -> Result<SubscriberName, String> {
+    /// Returns an instance of `SubscriberName` if the input satisfies all
+    /// our validation constraints on subscriber names, the first violated
+    /// constraint otherwise.
+    pub fn parse(s: String) -> Result<SubscriberName, SubscriberNameError> {
         // `.trim()` returns a view over the input `s` without trailing // whitespace-like
         // characters. `.is_empty` checks if the view contains any character.
-        let is_empty_or_whitespace = s.trim().is_empty();
+        if s.trim().is_empty() {
+            return Err(SubscriberNameError::Empty);
+        }
         // A grapheme is defined by the Unicode standard as a "user-perceived"
         // character: `å` is a single grapheme, but it is composed of two characters // (`a` and
         // `̊`).
@@ -16,18 +40,22 @@ impl SubscriberName {
         // `graphemes` returns an iterator over the graphemes in the input `s`.
         // `true` specifies that we want to use the extended grapheme definition set, // the
         // recommended one.
-        let is_too_long = s.graphemes(true).count() > 256;
+        let graphemes = s.graphemes(true).count();
+        if graphemes > MAX_LENGTH {
+            return Err(SubscriberNameError::TooLong {
+                graphemes,
+                max: MAX_LENGTH,
+            });
+        }
         // Iterate over all characters in the input `s` to check if any of them // matches one of
         // the characters in the forbidden array.
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = s
+        if let Some(character) = s
             .chars()
-            .any(|g| forbidden_characters.contains(&g));
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
-        } else {
-            Ok(Self(s))
+            .find(|c| FORBIDDEN_CHARACTERS.contains(c))
+        {
+            return Err(SubscriberNameError::ForbiddenCharacter { character });
         }
+        Ok(Self(s))
     }
 }
 
@@ -39,9 +67,96 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use claims::{assert_err, assert_ok};
+    use claims::{
+        assert_err_eq,
+        assert_ok,
+    };
+    use quickcheck::{
+        Arbitrary,
+        Gen,
+    };
+    use quickcheck_macros::quickcheck;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    use super::{
+        FORBIDDEN_CHARACTERS,
+        MAX_LENGTH,
+    };
+    use crate::domain::{
+        SubscriberName,
+        SubscriberNameError,
+    };
+
+    /// Graphemes names are made of: Latin, Cyrillic and CJK letters,
+    /// right-to-left scripts, combining marks and multi-codepoint emojis.
+    /// None of them starts with a combining mark, so that concatenating them
+    /// does not merge graphemes.
+    const GRAPHEMES: [&str; 20] = [
+        "a",
+        "Z",
+        "é",
+        "e\u{301}",
+        "a\u{302}\u{323}",
+        "ß",
+        "ё",
+        "名",
+        "李",
+        "ا",
+        "ب",
+        "ש",
+        "\u{5e9}\u{5c1}",
+        "ل",
+        "👩\u{200d}🚀",
+        "🇫🇷",
+        " ",
+        "-",
+        "'",
+        ".",
+    ];
+    const WHITESPACE: [char; 5] = [' ', '\t', '\n', '\u{a0}', '\u{3000}'];
 
-    use crate::domain::SubscriberName;
+    /// Between `min` and `max` graphemes, the first one not being whitespace.
+    fn graphemes(g: &mut Gen, min: usize, max: usize) -> Vec<&'static str> {
+        let length = min + usize::arbitrary(g) % (max - min + 1);
+        let mut graphemes = vec![*g
+            .choose(&GRAPHEMES[..16])
+            .unwrap()];
+        graphemes.extend((1..length).map(|_| *g.choose(&GRAPHEMES).unwrap()));
+        graphemes
+    }
+
+    // Both `Clone` and `Debug` are required by `quickcheck`
+    #[derive(Debug, Clone)]
+    struct ValidNameFixture(Vec<&'static str>);
+
+    impl Arbitrary for ValidNameFixture {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Self(graphemes(g, 1, MAX_LENGTH))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TooLongNameFixture(String);
+
+    impl Arbitrary for TooLongNameFixture {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Self(graphemes(g, MAX_LENGTH + 1, 2 * MAX_LENGTH).concat())
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct WhitespaceFixture(String);
+
+    impl Arbitrary for WhitespaceFixture {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let length = 1 + usize::arbitrary(g) % 10;
+            Self(
+                (0..length)
+                    .map(|_| *g.choose(&WHITESPACE).unwrap())
+                    .collect(),
+            )
+        }
+    }
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -52,26 +167,35 @@ mod tests {
     #[test]
     fn a_name_longer_than_256_graphemes_is_rejected() {
         let name = "a".repeat(257);
-        assert_err!(SubscriberName::parse(name));
+        assert_err_eq!(
+            SubscriberName::parse(name),
+            SubscriberNameError::TooLong {
+                graphemes: 257,
+                max: 256
+            }
+        );
     }
 
     #[test]
     fn whitespace_only_names_are_rejected() {
         let name = " ".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_err_eq!(SubscriberName::parse(name), SubscriberNameError::Empty);
     }
 
     #[test]
     fn empty_string_is_rejected() {
         let name = "".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_err_eq!(SubscriberName::parse(name), SubscriberNameError::Empty);
     }
 
     #[test]
     fn names_containing_an_invalid_character_are_rejected() {
-        for name in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
-            let name = name.to_string();
-            assert_err!(SubscriberName::parse(name));
+        for character in ['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
+            let name = format!("Ursula {}", character);
+            assert_err_eq!(
+                SubscriberName::parse(name),
+                SubscriberNameError::ForbiddenCharacter { character }
+            );
         }
     }
 
@@ -80,4 +204,46 @@ mod tests {
         let name = "Ursula Le Guin".to_string();
         assert_ok!(SubscriberName::parse(name));
     }
+
+    #[quickcheck]
+    fn valid_names_are_accepted_verbatim(name: ValidNameFixture) -> bool {
+        let name = name.0.concat();
+        SubscriberName::parse(name.clone()).is_ok_and(|parsed| parsed.as_ref() == name)
+    }
+
+    #[quickcheck]
+    fn names_with_a_forbidden_character_are_rejected(
+        name: ValidNameFixture,
+        position: usize,
+        character: usize,
+    ) -> bool {
+        let character = FORBIDDEN_CHARACTERS[character % FORBIDDEN_CHARACTERS.len()];
+        let replacement = character.to_string();
+        let mut graphemes: Vec<&str> = name.0;
+        let position = position % graphemes.len();
+        graphemes[position] = &replacement;
+        SubscriberName::parse(graphemes.concat()).err()
+            == Some(SubscriberNameError::ForbiddenCharacter { character })
+    }
+
+    #[quickcheck]
+    fn names_longer_than_the_maximum_are_rejected(name: TooLongNameFixture) -> bool {
+        let graphemes = name.0.graphemes(true).count();
+        SubscriberName::parse(name.0).err()
+            == Some(SubscriberNameError::TooLong {
+                graphemes,
+                max: MAX_LENGTH,
+            })
+    }
+
+    #[quickcheck]
+    fn whitespace_only_names_are_rejected_as_empty(name: WhitespaceFixture) -> bool {
+        SubscriberName::parse(name.0).err() == Some(SubscriberNameError::Empty)
+    }
+
+    #[quickcheck]
+    fn parsing_never_panics(name: String) -> bool {
+        let _ = SubscriberName::parse(name);
+        true
+    }
 }
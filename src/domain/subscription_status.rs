@@ -0,0 +1,164 @@
+//! src/domain/subscription_status.rs
+
+/// Where a subscriber stands, stored as the `subscription_status` Postgres
+/// enum.
+#[derive(sqlx::Type, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// The link in the confirmation email has not been followed yet.
+    PendingConfirmation,
+    Confirmed,
+    /// The subscriber asked to stop receiving our emails.
+    Unsubscribed,
+    /// Emails sent to the subscriber bounced.
+    Bounced,
+    /// The subscriber reported one of our emails as spam.
+    Complained,
+    /// The subscriber must never be emailed again.
+    Suppressed,
+}
+
+/// A subscriber cannot go from `from` to `to`.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("A subscription cannot go from `{from}` to `{to}`.")]
+pub struct InvalidStatusTransition {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("`{0}` is not a subscription status.")]
+pub struct UnknownSubscriptionStatus(String);
+
+impl SubscriptionStatus {
+    pub const ALL: [SubscriptionStatus; 6] = [
+        Self::PendingConfirmation,
+        Self::Confirmed,
+        Self::Unsubscribed,
+        Self::Bounced,
+        Self::Complained,
+        Self::Suppressed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PendingConfirmation => "pending_confirmation",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+            Self::Bounced => "bounced",
+            Self::Complained => "complained",
+            Self::Suppressed => "suppressed",
+        }
+    }
+
+    /// The single source of truth for the lifecycle of a subscription.
+    ///
+    /// Staying put is always allowed, so that e.g. following a confirmation
+    /// link twice is harmless. Once unsubscribed or bounced, subscribers have
+    /// to confirm again; once they complained, they can only be suppressed.
+    pub fn can_become(self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+
+        self == next
+            || matches!(
+                (self, next),
+                (
+                    PendingConfirmation,
+                    Confirmed | Unsubscribed | Bounced | Complained | Suppressed
+                ) | (Confirmed, Unsubscribed | Bounced | Complained | Suppressed)
+                    | (Unsubscribed | Bounced, PendingConfirmation | Suppressed)
+                    | (Complained, Suppressed)
+            )
+    }
+
+    pub fn transition_to(
+        self,
+        next: SubscriptionStatus,
+    ) -> Result<SubscriptionStatus, InvalidStatusTransition> {
+        if self.can_become(next) {
+            Ok(next)
+        } else {
+            Err(InvalidStatusTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SubscriptionStatus {
+    type Err = UnknownSubscriptionStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| UnknownSubscriptionStatus(s.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{
+        assert_err,
+        assert_ok_eq,
+    };
+
+    use super::SubscriptionStatus::{
+        self,
+        *,
+    };
+
+    #[test]
+    fn statuses_round_trip_through_their_string_representation() {
+        for status in SubscriptionStatus::ALL {
+            assert_ok_eq!(
+                status
+                    .as_str()
+                    .parse::<SubscriptionStatus>(),
+                status
+            );
+        }
+        assert_err!("pending".parse::<SubscriptionStatus>());
+    }
+
+    #[test]
+    fn pending_subscriptions_can_be_confirmed_but_not_confirmed_ones_pending() {
+        assert_ok_eq!(PendingConfirmation.transition_to(Confirmed), Confirmed);
+        assert_ok_eq!(Confirmed.transition_to(Confirmed), Confirmed);
+        assert_err!(Confirmed.transition_to(PendingConfirmation));
+    }
+
+    #[test]
+    fn unsubscribed_and_bounced_subscribers_must_confirm_again() {
+        for status in [Unsubscribed, Bounced] {
+            assert!(status.can_become(PendingConfirmation));
+            assert!(!status.can_become(Confirmed));
+        }
+    }
+
+    #[test]
+    fn suppressed_is_final_and_reachable_from_everywhere() {
+        for status in SubscriptionStatus::ALL {
+            assert!(status.can_become(Suppressed));
+            assert_eq!(Suppressed.can_become(status), status == Suppressed);
+        }
+    }
+
+    #[test]
+    fn complaints_can_only_lead_to_suppression() {
+        for status in SubscriptionStatus::ALL {
+            assert_eq!(
+                Complained.can_become(status),
+                matches!(status, Complained | Suppressed)
+            );
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! src/email_checks.rs
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::Duration,
+};
+
+use hickory_resolver::{
+    error::ResolveErrorKind,
+    system_conf::read_system_conf,
+    TokioAsyncResolver,
+};
+
+use crate::{
+    configuration::SubscriberEmailSettings,
+    domain::{
+        SubscriberEmail,
+        SubscriberEmailError,
+    },
+};
+
+/// Finds out whether a domain can receive emails.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    /// `Ok(false)` if the domain definitely cannot receive emails, errors if
+    /// we could not find out.
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Asks the DNS servers configured on the machine.
+pub struct DnsMxResolver(TokioAsyncResolver);
+
+impl DnsMxResolver {
+    pub fn new(timeout: Duration) -> Self {
+        let (config, mut options) = read_system_conf().unwrap_or_else(|e| {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to read the system DNS configuration, using the default name servers."
+            );
+            Default::default()
+        });
+        options.timeout = timeout;
+        Self(TokioAsyncResolver::tokio(config, options))
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for DnsMxResolver {
+    #[tracing::instrument(name = "Look up the mail exchangers of a domain", skip(self))]
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // Fully qualified, so that search domains are not tried.
+        let domain = format!("{}.", domain);
+        match self
+            .0
+            .mx_lookup(domain.as_str())
+            .await
+        {
+            // A single `.` exchanger is a "null MX": the domain explicitly
+            // does not accept emails (RFC 7505).
+            Ok(exchangers) => Ok(exchangers
+                .iter()
+                .any(|mx| !mx.exchange().is_root())),
+            // Without MX records, emails are delivered to the domain itself
+            // (RFC 5321, section 5.1).
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                match self
+                    .0
+                    .lookup_ip(domain.as_str())
+                    .await
+                {
+                    Ok(_) => Ok(true),
+                    Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                        Ok(false)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Knows a fixed set of domains that accept emails, to keep tests off the
+/// network.
+#[derive(Default)]
+pub struct StubMxResolver {
+    domains: HashSet<String>,
+}
+
+impl StubMxResolver {
+    pub fn new<I, S>(domains: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            domains: domains
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for StubMxResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.domains.contains(domain))
+    }
+}
+
+/// The checks applied to the email of would-be subscribers, on top of it
+/// being well-formed.
+pub struct EmailChecks {
+    strip_plus_addressing: bool,
+    reject_disposable_domains: bool,
+    mx_resolver: Option<Arc<dyn MxResolver>>,
+}
+
+impl EmailChecks {
+    pub fn new(settings: &SubscriberEmailSettings) -> Self {
+        let mx_resolver = settings
+            .check_mx_records
+            .then(|| Arc::new(DnsMxResolver::new(settings.dns_timeout())) as Arc<dyn MxResolver>);
+        Self {
+            strip_plus_addressing: settings.strip_plus_addressing,
+            reject_disposable_domains: settings.reject_disposable_domains,
+            mx_resolver,
+        }
+    }
+
+    /// Look up mail exchangers with `mx_resolver` instead of the DNS.
+    pub fn with_mx_resolver(mut self, mx_resolver: impl MxResolver + 'static) -> Self {
+        self.mx_resolver = Some(Arc::new(mx_resolver));
+        self
+    }
+
+    /// Returns the email to store, which may differ from `email` if plus
+    /// addressing is stripped.
+    ///
+    /// If the DNS cannot be queried the email is let through: we would rather
+    /// send an email that bounces than turn a subscriber away.
+    pub async fn check(
+        &self,
+        email: SubscriberEmail,
+    ) -> Result<SubscriberEmail, SubscriberEmailError> {
+        let email = if self.strip_plus_addressing {
+            email.without_plus_addressing()
+        } else {
+            email
+        };
+        if self.reject_disposable_domains && email.is_disposable() {
+            return Err(SubscriberEmailError::DisposableDomain {
+                domain: email.domain().into(),
+            });
+        }
+        if let Some(mx_resolver) = &self.mx_resolver {
+            match mx_resolver
+                .accepts_mail(email.domain())
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(SubscriberEmailError::UndeliverableDomain {
+                        domain: email.domain().into(),
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to look up the mail exchangers of a domain, letting the email through."
+                    );
+                }
+            }
+        }
+        Ok(email)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{
+        assert_err_eq,
+        assert_ok,
+    };
+
+    use super::{
+        EmailChecks,
+        MxResolver,
+        StubMxResolver,
+    };
+    use crate::{
+        configuration::SubscriberEmailSettings,
+        domain::{
+            SubscriberEmail,
+            SubscriberEmailError,
+        },
+    };
+
+    struct FailingMxResolver;
+    #[async_trait::async_trait]
+    impl MxResolver for FailingMxResolver {
+        async fn accepts_mail(&self, _domain: &str) -> Result<bool, anyhow::Error> {
+            Err(anyhow::anyhow!("The name server timed out."))
+        }
+    }
+
+    fn email(email: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(email.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn no_checks_are_enabled_by_default() {
+        let checks = EmailChecks::new(&SubscriberEmailSettings::default());
+
+        let checked = checks
+            .check(email("ursula+news@mailinator.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(checked.as_ref(), "ursula+news@mailinator.com");
+    }
+
+    #[tokio::test]
+    async fn plus_addressing_is_stripped_if_enabled() {
+        let checks = EmailChecks::new(&SubscriberEmailSettings {
+            strip_plus_addressing: true,
+            ..Default::default()
+        });
+
+        let checked = checks
+            .check(email("ursula+news@example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(checked.as_ref(), "ursula@example.com");
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_are_rejected_if_enabled() {
+        let checks = EmailChecks::new(&SubscriberEmailSettings {
+            reject_disposable_domains: true,
+            ..Default::default()
+        });
+
+        assert_err_eq!(
+            checks
+                .check(email("ursula@yopmail.com"))
+                .await,
+            SubscriberEmailError::DisposableDomain {
+                domain: "yopmail.com".into()
+            }
+        );
+        assert_ok!(
+            checks
+                .check(email("ursula@example.com"))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn domains_that_cannot_receive_emails_are_rejected() {
+        let checks = EmailChecks::new(&SubscriberEmailSettings::default())
+            .with_mx_resolver(StubMxResolver::new(["example.com"]));
+
+        assert_ok!(
+            checks
+                .check(email("ursula@example.com"))
+                .await
+        );
+        assert_err_eq!(
+            checks
+                .check(email("ursula@example.invalid"))
+                .await,
+            SubscriberEmailError::UndeliverableDomain {
+                domain: "example.invalid".into()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn emails_are_let_through_if_the_dns_cannot_be_queried() {
+        let checks = EmailChecks::new(&SubscriberEmailSettings::default())
+            .with_mx_resolver(FailingMxResolver);
+
+        assert_ok!(
+            checks
+                .check(email("ursula@example.com"))
+                .await
+        );
+    }
+}
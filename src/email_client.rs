@@ -5,7 +5,11 @@ use secrecy::{
     Secret,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    metrics,
+    telemetry::inject_trace_context,
+};
 
 #[derive(Debug)]
 pub struct EmailClient {
@@ -50,8 +54,14 @@ impl EmailClient {
             text_body: text_content,
         };
 
-        self.http_client
+        let mut trace_context = reqwest::header::HeaderMap::new();
+        inject_trace_context(&mut trace_context);
+
+        let started_at = std::time::Instant::now();
+        let outcome = self
+            .http_client
             .post(url)
+            .headers(trace_context)
             .header("X-Requested-With", "XMLHttpRequest")
             .header(
                 "Authorization",
@@ -63,9 +73,22 @@ impl EmailClient {
             )
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .and_then(|response| response.error_for_status());
+        metrics::record_email_send(outcome.is_ok(), started_at);
+        outcome?;
+
+        Ok(())
+    }
 
+    /// Check that the email provider can be reached.
+    ///
+    /// Any HTTP response, error statuses included, proves that it can.
+    pub async fn ping(&self) -> Result<(), reqwest::Error> {
+        self.http_client
+            .get(&self.base_url)
+            .send()
+            .await?;
         Ok(())
     }
 }
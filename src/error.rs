@@ -0,0 +1,395 @@
+//! src/error.rs
+//!
+//! How errors reach clients: as RFC 7807 `application/problem+json` bodies
+//! carrying a stable code and the ID of the request. What went wrong in
+//! detail only makes it to our logs.
+use actix_web::{
+    body::{
+        BoxBody,
+        MessageBody,
+    },
+    dev::{
+        ServiceRequest,
+        ServiceResponse,
+    },
+    error::InternalError,
+    http::{
+        header::{
+            self,
+            HeaderValue,
+        },
+        StatusCode,
+    },
+    middleware::Next,
+    HttpMessage,
+    HttpResponse,
+    ResponseError,
+};
+use tracing_actix_web::RequestId;
+use uuid::Uuid;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+tokio::task_local! {
+    /// Set by `problem_details` for the duration of each request.
+    static REQUEST_ID: Option<Uuid>;
+}
+
+/// An error described to clients as problem details.
+///
+/// Implementors render themselves with `problem_response` in
+/// `ResponseError::error_response`.
+pub trait Problem: ResponseError {
+    /// Tells problems apart. Clients match on it: never change it.
+    fn code(&self) -> &'static str;
+
+    /// What the client should know about the problem. Unexpected errors
+    /// keep their details to our logs, hence the default.
+    fn detail(&self) -> Option<String> {
+        None
+    }
+
+    /// Members specific to the problem, added to the body.
+    fn extensions(&self) -> serde_json::Map<String, serde_json::Value> {
+        serde_json::Map::new()
+    }
+}
+
+/// The body of every error response.
+#[derive(serde::Serialize, utoipa::ToSchema, Debug)]
+pub struct ProblemDetails {
+    /// Always `about:blank`: `code` identifies the problem.
+    #[serde(rename = "type")]
+    #[schema(example = "about:blank")]
+    type_: &'static str,
+    /// The reason phrase of `status`.
+    #[schema(example = "Bad Request")]
+    title: &'static str,
+    #[schema(example = 400)]
+    status: u16,
+    /// A stable, machine-readable identifier of the problem.
+    #[schema(example = "validation_failed")]
+    code: String,
+    /// A human-readable explanation, in English.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    /// The ID our logs know the request by: quote it when reporting an
+    /// issue.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(format = "uuid")]
+    request_id: Option<String>,
+    /// Members specific to the problem, e.g. the invalid `field` of a form.
+    #[serde(flatten)]
+    #[schema(ignore)]
+    extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ProblemDetails {
+    /// Problem details for the request being served.
+    pub fn new(status: StatusCode, code: impl Into<String>) -> Self {
+        Self {
+            type_: "about:blank",
+            title: status
+                .canonical_reason()
+                .unwrap_or("Error"),
+            status: status.as_u16(),
+            code: code.into(),
+            detail: None,
+            request_id: REQUEST_ID
+                .try_with(|request_id| *request_id)
+                .ok()
+                .flatten()
+                .map(|request_id| request_id.to_string()),
+            extensions: serde_json::Map::new(),
+        }
+    }
+
+    /// Problem details for a status code alone, e.g. `not_found` for a `404`.
+    pub fn for_status(status: StatusCode) -> Self {
+        let code = status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+            .replace([' ', '-'], "_");
+        Self::new(status, code)
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn extensions(mut self, extensions: serde_json::Map<String, serde_json::Value>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type(PROBLEM_JSON)
+            .json(self)
+    }
+}
+
+/// Render `problem` as problem details.
+pub fn problem_response<P: Problem + ?Sized>(problem: &P) -> HttpResponse {
+    let mut details =
+        ProblemDetails::new(problem.status_code(), problem.code()).extensions(problem.extensions());
+    if let Some(detail) = problem.detail() {
+        details = details.detail(detail);
+    }
+    details.into_response()
+}
+
+/// Make sure every error response is problem details.
+///
+/// Must be wrapped by `TracingLogger`, whose request ID it hands to
+/// `ProblemDetails::new`. Error responses that are neither problem details
+/// nor meant for a browser (e.g. those of extractors, or of unknown routes)
+/// are replaced by the problem details of their status code; only those of
+/// client errors explain what went wrong.
+pub async fn problem_details(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|request_id| Uuid::from(*request_id));
+    REQUEST_ID
+        .scope(request_id, async move {
+            match next.call(req).await {
+                Ok(response) => {
+                    let (req, response) = response.into_parts();
+                    Ok(ServiceResponse::new(
+                        req,
+                        ensure_problem_details(response.map_into_boxed_body()),
+                    ))
+                }
+                // Render the errors of inner middlewares while the request ID
+                // is still around.
+                Err(e) => {
+                    let response = ensure_problem_details(e.error_response());
+                    Err(InternalError::from_response(e, response).into())
+                }
+            }
+        })
+        .await
+}
+
+fn ensure_problem_details(response: HttpResponse) -> HttpResponse {
+    if !needs_problem_details(&response) {
+        return response;
+    }
+    let status = response.status();
+    let mut details = ProblemDetails::for_status(status);
+    if let (true, Some(e)) = (status.is_client_error(), response.error()) {
+        details = details.detail(e.to_string());
+    }
+    response.map_body(|head, _| {
+        head.headers
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        BoxBody::new(serde_json::to_string(&details).unwrap_or_default())
+    })
+}
+
+/// Error responses without a body, or with the plain text actix-web renders
+/// errors as by default.
+fn needs_problem_details(response: &HttpResponse) -> bool {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return false;
+    }
+    match response
+        .headers()
+        .get(header::CONTENT_TYPE)
+    {
+        None => true,
+        Some(content_type) => content_type
+            .as_bytes()
+            .starts_with(b"text/plain"),
+    }
+}
+
+/// Format `e` followed by the chain of its causes, one per line.
+///
+/// Meant for `Debug` implementations, so that logging an error shows what
+/// caused it.
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        web,
+        App,
+        HttpResponse,
+        ResponseError,
+    };
+
+    use super::{
+        error_chain_fmt,
+        problem_response,
+        Problem,
+        ProblemDetails,
+    };
+
+    #[derive(thiserror::Error)]
+    #[error("Failed to confirm a subscription.")]
+    struct OuterError(#[source] std::io::Error);
+
+    impl std::fmt::Debug for OuterError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            error_chain_fmt(self, f)
+        }
+    }
+
+    #[test]
+    fn every_cause_is_listed_after_the_error() {
+        let error = OuterError(std::io::Error::other("connection refused"));
+
+        assert_eq!(
+            format!("{:?}", error),
+            "Failed to confirm a subscription.\n\nCaused by:\n\tconnection refused\n"
+        );
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("The newsletter issue was already published.")]
+    struct AlreadyPublished;
+
+    impl ResponseError for AlreadyPublished {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::CONFLICT
+        }
+
+        fn error_response(&self) -> HttpResponse {
+            problem_response(self)
+        }
+    }
+
+    impl Problem for AlreadyPublished {
+        fn code(&self) -> &'static str {
+            "already_published"
+        }
+
+        fn detail(&self) -> Option<String> {
+            Some(self.to_string())
+        }
+
+        fn extensions(&self) -> serde_json::Map<String, serde_json::Value> {
+            serde_json::json!({ "issue": 42 })
+                .as_object()
+                .cloned()
+                .unwrap()
+        }
+    }
+
+    #[actix_web::test]
+    async fn problems_are_rendered_as_problem_json() {
+        let response = problem_response(&AlreadyPublished);
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response
+                .headers()
+                .get("Content-Type")
+                .unwrap(),
+            "application/problem+json"
+        );
+        let body: serde_json::Value = serde_json::from_slice(
+            &actix_web::body::to_bytes(response.into_body())
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        // Outside of a request, there is no request ID to report.
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Conflict",
+                "status": 409,
+                "code": "already_published",
+                "detail": "The newsletter issue was already published.",
+                "issue": 42,
+            })
+        );
+    }
+
+    #[test]
+    fn codes_of_bare_status_codes_are_their_snake_cased_reason() {
+        for (status, code) in [
+            (StatusCode::NOT_FOUND, "not_found"),
+            (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal_server_error"),
+        ] {
+            let body = serde_json::to_value(ProblemDetails::for_status(status)).unwrap();
+            assert_eq!(body["code"], code);
+        }
+    }
+
+    #[actix_web::test]
+    async fn extractor_errors_and_unknown_routes_become_problems_with_a_request_id() {
+        #[derive(serde::Deserialize)]
+        struct Query {
+            #[allow(dead_code)]
+            page: u32,
+        }
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(actix_web::middleware::from_fn(super::problem_details))
+                .wrap(tracing_actix_web::TracingLogger::default())
+                .route(
+                    "/issues",
+                    web::get().to(|_: web::Query<Query>| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+
+        for (uri, status, code) in [
+            ("/issues?page=first", 400, "bad_request"),
+            ("/unknown", 404, "not_found"),
+        ] {
+            let response = actix_web::test::call_service(
+                &app,
+                actix_web::test::TestRequest::get()
+                    .uri(uri)
+                    .to_request(),
+            )
+            .await;
+
+            assert_eq!(response.status().as_u16(), status);
+            assert_eq!(
+                response
+                    .headers()
+                    .get("Content-Type")
+                    .unwrap(),
+                "application/problem+json"
+            );
+            let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+            assert_eq!(body["code"], code);
+            assert_eq!(body["status"], status);
+            assert!(body["request_id"].is_string());
+            // Only client errors come with an explanation.
+            assert_eq!(body["detail"].is_string(), status == 400);
+        }
+    }
+}
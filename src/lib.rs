@@ -1,10 +1,22 @@
 //! lib.rs
 //! // hello
+pub mod authentication;
+pub mod bot_protection;
+pub mod cli;
 pub mod configuration;
+pub mod email_checks;
 pub mod email_client;
+pub mod error;
+pub mod metrics;
+pub mod migrations;
+pub mod openapi;
+pub mod rate_limiting;
+pub mod repositories;
 pub mod routes;
 pub mod startup;
 
 pub mod telemetry;
+#[cfg(feature = "testkit")]
+pub mod testkit;
 
 pub mod domain;
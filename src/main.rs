@@ -1,21 +1,39 @@
 //! main.rs
+use anyhow::Context;
+use clap::Parser;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use zero2prod::{
-    configuration::get_configuration,
-    startup::Application,
+    cli::{
+        self,
+        Cli,
+        Command,
+    },
+    configuration::get_configuration_from,
     telemetry::{
         get_subscriber,
         init_subscriber,
+        shutdown_telemetry,
     },
 };
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration).await?;
-    application
-        .run_until_stopped()
-        .await?;
-    Ok(())
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let command = cli
+        .command
+        .unwrap_or(Command::Serve);
+    let configuration = get_configuration_from(cli.config.as_deref())?;
+    // Subcommands other than `serve` print their output to stdout: keep logs
+    // out of the way.
+    let sink = if matches!(command, Command::Serve) {
+        BoxMakeWriter::new(std::io::stdout)
+    } else {
+        BoxMakeWriter::new(std::io::stderr)
+    };
+    let subscriber = get_subscriber("zero2prod".into(), sink, &configuration.telemetry)
+        .context("Failed to set up telemetry.")?;
+    init_subscriber(subscriber).context("Failed to set up telemetry.")?;
+    let outcome = cli::run(command, configuration).await;
+    shutdown_telemetry();
+    outcome
 }
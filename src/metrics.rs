@@ -0,0 +1,250 @@
+//! src/metrics.rs
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{
+        ServiceRequest,
+        ServiceResponse,
+    },
+    http::header::ContentType,
+    middleware::Next,
+    web,
+    HttpResponse,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder,
+    Histogram,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use sqlx::{
+    PgPool,
+    Postgres,
+    Transaction,
+};
+
+/// The registry backing `/metrics`.
+///
+/// We do not use `prometheus`'s default registry to keep full control over
+/// what gets exposed.
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests served."),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent serving HTTP requests.",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new(
+            "db_pool_connections",
+            "Number of connections currently held by the database pool.",
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_IDLE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new(
+            "db_pool_idle_connections",
+            "Number of idle connections in the database pool.",
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_IN_USE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new(
+            "db_pool_in_use_connections",
+            "Number of database connections currently checked out of the pool.",
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_ACQUIRE_WAITS_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(
+        IntCounter::new(
+            "db_pool_acquire_waits_total",
+            "Number of connection acquisitions that found every connection of the pool in use.",
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_ACQUIRE_DURATION_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register(
+        Histogram::with_opts(HistogramOpts::new(
+            "db_pool_acquire_duration_seconds",
+            "Time spent waiting for a connection from the database pool.",
+        ))
+        .unwrap(),
+    )
+});
+
+static EMAILS_SENT_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "emails_sent_total",
+                "Number of emails handed over to the email provider.",
+            ),
+            &["outcome"],
+        )
+        .unwrap(),
+    )
+});
+
+static EMAIL_SEND_DURATION_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register(
+        Histogram::with_opts(HistogramOpts::new(
+            "email_send_duration_seconds",
+            "Time spent waiting for the email provider to accept an email.",
+        ))
+        .unwrap(),
+    )
+});
+
+static HONEYPOT_SUBMISSIONS_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(
+        IntCounter::new(
+            "honeypot_submissions_total",
+            "Number of form submissions ignored because they filled in the honeypot.",
+        )
+        .unwrap(),
+    )
+});
+
+fn register<C>(collector: C) -> C
+where
+    C: prometheus::core::Collector + Clone + 'static,
+{
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("Failed to register a metric.");
+    collector
+}
+
+/// Record the outcome and the duration of every HTTP request.
+///
+/// Requests are labelled with the route pattern they matched (e.g.
+/// `/subscriptions/confirm`), not with their raw path, to keep the number of
+/// time series bounded.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".into());
+
+    let response = next.call(req).await?;
+
+    let status = response
+        .status()
+        .as_u16()
+        .to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&labels)
+        .inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    Ok(response)
+}
+
+/// Begin a transaction on `pool`, recording whether we had to wait for a
+/// connection and for how long: sqlx does not report it.
+///
+/// Opening a new connection is not waiting: only a pool that already holds
+/// `max_connections`, none of them idle, makes us wait for another task to
+/// give one back.
+pub async fn begin_transaction(
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    if pool.num_idle() == 0
+        && pool.size()
+            >= pool
+                .options()
+                .get_max_connections()
+    {
+        DB_POOL_ACQUIRE_WAITS_TOTAL.inc();
+    }
+    let start = Instant::now();
+    let transaction = pool.begin().await;
+    DB_POOL_ACQUIRE_DURATION_SECONDS.observe(start.elapsed().as_secs_f64());
+    transaction
+}
+
+/// Record the outcome and the duration of an attempt to send an email.
+pub fn record_email_send(succeeded: bool, started_at: Instant) {
+    let outcome = if succeeded { "success" } else { "failure" };
+    EMAILS_SENT_TOTAL
+        .with_label_values(&[outcome])
+        .inc();
+    EMAIL_SEND_DURATION_SECONDS.observe(
+        started_at
+            .elapsed()
+            .as_secs_f64(),
+    );
+}
+
+/// Record a form submission ignored because it filled in the honeypot.
+pub fn record_honeypot_submission() {
+    HONEYPOT_SUBMISSIONS_TOTAL.inc();
+}
+
+/// Expose all metrics in the Prometheus text format.
+pub async fn metrics(pool: web::Data<PgPool>) -> HttpResponse {
+    // Pool gauges are sampled on scrape rather than kept up to date.
+    let size = i64::from(pool.size());
+    let idle = pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.set(size);
+    DB_POOL_IDLE_CONNECTIONS.set(idle);
+    DB_POOL_IN_USE_CONNECTIONS.set(size - idle);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!(error.cause_chain = ?e, "Failed to encode metrics.");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType(
+            TextEncoder::new()
+                .format_type()
+                .parse()
+                .unwrap(),
+        ))
+        .body(buffer)
+}
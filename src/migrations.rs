@@ -0,0 +1,85 @@
+//! src/migrations.rs
+use sqlx::{
+    migrate::{
+        MigrateError,
+        Migrator,
+    },
+    PgConnection,
+    PgPool,
+};
+
+/// The migrations in `migrations/`, embedded in the binary at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// The key of the advisory lock serialising instances that migrate the same
+/// database on startup.
+const MIGRATIONS_LOCK_ID: i64 = 0x7a65_726f_3270_726f; // "zero2pro"
+
+#[derive(thiserror::Error, Debug)]
+pub enum MigrationError {
+    #[error(
+        "The database schema is newer than this binary: migration(s) {0:?} were applied by a \
+         more recent release."
+    )]
+    SchemaTooNew(Vec<i64>),
+    #[error("Failed to talk to the database while migrating it.")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to apply the migrations.")]
+    Migrate(#[from] MigrateError),
+}
+
+/// Bring the database schema up to date with the migrations embedded in the
+/// binary.
+///
+/// Instances starting concurrently queue on an advisory lock: the first one
+/// applies the migrations, the others find nothing left to do.
+#[tracing::instrument(name = "Run database migrations", skip(pool))]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrationError> {
+    let mut connection = pool.acquire().await?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATIONS_LOCK_ID)
+        .execute(&mut *connection)
+        .await?;
+    let outcome = migrate(&mut connection).await;
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATIONS_LOCK_ID)
+        .execute(&mut *connection)
+        .await?;
+    outcome
+}
+
+async fn migrate(connection: &mut PgConnection) -> Result<(), MigrationError> {
+    let unknown = unknown_applied_migrations(connection).await?;
+    if !unknown.is_empty() {
+        return Err(MigrationError::SchemaTooNew(unknown));
+    }
+    MIGRATOR
+        .run(connection)
+        .await?;
+    Ok(())
+}
+
+/// The migrations recorded as applied that this binary knows nothing about.
+async fn unknown_applied_migrations(
+    connection: &mut PgConnection,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let has_migrations_table: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&mut *connection)
+            .await?;
+    if !has_migrations_table {
+        return Ok(vec![]);
+    }
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(&mut *connection)
+            .await?;
+    Ok(applied
+        .into_iter()
+        .filter(|version| {
+            !MIGRATOR
+                .iter()
+                .any(|m| m.version == *version)
+        })
+        .collect())
+}
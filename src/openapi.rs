@@ -0,0 +1,42 @@
+//! src/openapi.rs
+use utoipa::OpenApi;
+
+use crate::{
+    bot_protection::BotCheckError,
+    domain::{
+        NewSubscriberError,
+        SubscriberEmailError,
+        SubscriberNameError,
+    },
+    routes,
+};
+
+/// The OpenAPI document describing our public API.
+///
+/// Every route of `startup::api_routes` marked as documented must be listed
+/// in `paths`, and no other: `tests/api/openapi.rs` fails if the two drift
+/// apart.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "A newsletter subscription service."),
+    paths(
+        routes::health_check::health_check,
+        routes::health_check::liveness,
+        routes::health_check::readiness,
+        routes::subscriptions::subscribe,
+        routes::confirm,
+        routes::publish_newsletter,
+    ),
+    components(schemas(
+        NewSubscriberError,
+        SubscriberNameError,
+        SubscriberEmailError,
+        BotCheckError
+    )),
+    tags(
+        (name = "health", description = "Probes used by our hosting platform."),
+        (name = "subscriptions", description = "Subscribing to the newsletter."),
+        (name = "newsletters", description = "Publishing newsletter issues."),
+    )
+)]
+pub struct ApiDoc;
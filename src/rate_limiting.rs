@@ -0,0 +1,595 @@
+//! src/rate_limiting.rs
+use std::{
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::{
+    body::{
+        EitherBody,
+        MessageBody,
+    },
+    dev::{
+        ServiceRequest,
+        ServiceResponse,
+    },
+    http::{
+        header::{
+            HeaderMap,
+            RETRY_AFTER,
+            X_FORWARDED_FOR,
+        },
+        StatusCode,
+    },
+    middleware::Next,
+    web,
+    HttpRequest,
+};
+use anyhow::Context;
+use hashlink::LruCache;
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{
+        RateLimitingBackend,
+        RateLimitingSettings,
+        TokenBucketSettings,
+    },
+    error::ProblemDetails,
+    metrics::begin_transaction,
+};
+
+/// At most this many buckets are kept in memory: the least recently used one
+/// is forgotten, i.e. refilled, to make room for a new one.
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// Stale Postgres buckets are deleted on one take out of this many, on
+/// average.
+const POSTGRES_PRUNE_ONE_IN: u32 = 1_000;
+
+/// Enforces the limits configured for each route, using token buckets.
+pub struct RateLimiter {
+    settings: RateLimitingSettings,
+    store: BucketStore,
+    /// How long the slowest bucket takes to fill up again: buckets untouched
+    /// for longer are full and can be deleted.
+    stale_after: Duration,
+}
+
+enum BucketStore {
+    Memory(Mutex<LruCache<String, MemoryBucket>>),
+    Postgres(PgPool),
+}
+
+struct MemoryBucket {
+    tokens: f64,
+    updated_at: Instant,
+    /// When the bucket will be full again, i.e. indistinguishable from a
+    /// missing one.
+    full_at: Instant,
+}
+
+/// The request was rejected: the bucket will have a token again after
+/// `retry_after`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitingSettings, pool: PgPool) -> Self {
+        let store = match settings.backend {
+            RateLimitingBackend::Memory => {
+                BucketStore::Memory(Mutex::new(LruCache::new(MAX_MEMORY_BUCKETS)))
+            }
+            RateLimitingBackend::Postgres => BucketStore::Postgres(pool),
+        };
+        let stale_after = settings
+            .routes
+            .values()
+            .flat_map(|limits| [limits.per_ip, limits.per_email, limits.per_email_domain])
+            .flatten()
+            .map(time_to_fill)
+            .max()
+            .unwrap_or_default();
+        Self {
+            settings,
+            store,
+            stale_after,
+        }
+    }
+
+    /// Take a token from the bucket identified by `key`.
+    ///
+    /// If the shared store is unavailable the request is let through: we
+    /// would rather be spammed for a while than refuse every subscription.
+    pub async fn take(&self, key: &str, limit: TokenBucketSettings) -> Result<(), RateLimited> {
+        match &self.store {
+            BucketStore::Memory(buckets) => {
+                take_from_memory(&mut buckets.lock().unwrap(), key, limit)
+            }
+            BucketStore::Postgres(pool) => {
+                if rand::thread_rng().gen_ratio(1, POSTGRES_PRUNE_ONE_IN) {
+                    if let Err(e) = delete_stale_buckets(pool, self.stale_after).await {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            "Failed to delete stale rate limit buckets."
+                        );
+                    }
+                }
+                match take_from_postgres(pool, key, limit).await {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            "Failed to check a rate limit, letting the request through."
+                        );
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Put back a token taken from the bucket identified by `key`, because
+    /// another bucket rejected the request.
+    pub async fn refund(&self, key: &str, limit: TokenBucketSettings) {
+        match &self.store {
+            BucketStore::Memory(buckets) => {
+                refund_to_memory(&mut buckets.lock().unwrap(), key, limit)
+            }
+            BucketStore::Postgres(pool) => {
+                if let Err(e) = refund_to_postgres(pool, key, limit).await {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to refund a rate limit token."
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// How long an empty bucket takes to fill up.
+fn time_to_fill(limit: TokenBucketSettings) -> Duration {
+    Duration::from_secs_f64(f64::from(limit.capacity) / (limit.refill_per_minute / 60.0))
+}
+
+/// Refill a bucket holding `tokens` after `elapsed`, then try to take a token
+/// out of it.
+///
+/// Returns how many tokens are left in the bucket.
+fn take_token(
+    tokens: f64,
+    elapsed: Duration,
+    limit: TokenBucketSettings,
+) -> (f64, Result<(), RateLimited>) {
+    let refill_per_second = limit.refill_per_minute / 60.0;
+    let tokens = (tokens + elapsed.as_secs_f64() * refill_per_second).min(limit.capacity.into());
+    if tokens >= 1.0 {
+        (tokens - 1.0, Ok(()))
+    } else {
+        let retry_after = Duration::from_secs_f64((1.0 - tokens) / refill_per_second);
+        (tokens, Err(RateLimited { retry_after }))
+    }
+}
+
+fn take_from_memory(
+    buckets: &mut LruCache<String, MemoryBucket>,
+    key: &str,
+    limit: TokenBucketSettings,
+) -> Result<(), RateLimited> {
+    let now = Instant::now();
+    // Forget the least recently used buckets as long as they are full again:
+    // each bucket is dropped at most once, so this is cheap on average.
+    while buckets
+        .iter()
+        .next()
+        .is_some_and(|(_, bucket)| bucket.full_at <= now)
+    {
+        buckets.remove_lru();
+    }
+    let (tokens, elapsed) = buckets
+        .peek(key)
+        .map(|bucket| (bucket.tokens, now - bucket.updated_at))
+        .unwrap_or((limit.capacity.into(), Duration::ZERO));
+    let (tokens, outcome) = take_token(tokens, elapsed, limit);
+    buckets.insert(key.to_owned(), memory_bucket(tokens, now, limit));
+    outcome
+}
+
+fn refund_to_memory(
+    buckets: &mut LruCache<String, MemoryBucket>,
+    key: &str,
+    limit: TokenBucketSettings,
+) {
+    if let Some(bucket) = buckets.peek_mut(key) {
+        let tokens = (bucket.tokens + 1.0).min(limit.capacity.into());
+        *bucket = memory_bucket(tokens, bucket.updated_at, limit);
+    }
+}
+
+fn memory_bucket(tokens: f64, updated_at: Instant, limit: TokenBucketSettings) -> MemoryBucket {
+    let until_full = (f64::from(limit.capacity) - tokens) / (limit.refill_per_minute / 60.0);
+    MemoryBucket {
+        tokens,
+        updated_at,
+        full_at: updated_at + Duration::from_secs_f64(until_full),
+    }
+}
+
+#[tracing::instrument(name = "Take a token from a shared bucket", skip(pool, limit))]
+async fn take_from_postgres(
+    pool: &PgPool,
+    key: &str,
+    limit: TokenBucketSettings,
+) -> Result<Result<(), RateLimited>, anyhow::Error> {
+    let mut transaction = begin_transaction(pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // Create a full bucket if there is none, then lock it until we are done.
+    let bucket = sqlx::query!(
+        r#"INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (key) DO UPDATE SET key = EXCLUDED.key
+        RETURNING tokens, updated_at, now() AS "now!""#,
+        key,
+        f64::from(limit.capacity),
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch the bucket.")?;
+    let elapsed = (bucket.now - bucket.updated_at)
+        .to_std()
+        .unwrap_or_default();
+    let (tokens, outcome) = take_token(bucket.tokens, elapsed, limit);
+    sqlx::query!(
+        r#"UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3 WHERE key = $1"#,
+        key,
+        tokens,
+        bucket.now,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the bucket.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the bucket update.")?;
+    Ok(outcome)
+}
+
+#[tracing::instrument(name = "Refund a token to a shared bucket", skip(pool, limit))]
+async fn refund_to_postgres(
+    pool: &PgPool,
+    key: &str,
+    limit: TokenBucketSettings,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE rate_limit_buckets SET tokens = LEAST(tokens + 1, $2) WHERE key = $1"#,
+        key,
+        f64::from(limit.capacity),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Delete the shared buckets that have not been touched for `stale_after`:
+/// they are full again, so a missing row means the same thing.
+///
+/// Returns how many buckets were deleted.
+#[tracing::instrument(name = "Delete stale rate limit buckets", skip(pool))]
+pub async fn delete_stale_buckets(
+    pool: &PgPool,
+    stale_after: Duration,
+) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM rate_limit_buckets WHERE updated_at < now() - make_interval(secs => $1)"#,
+        stale_after.as_secs_f64(),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+/// The address of the client that sent `req`, as told by the proxy in front
+/// of us if `trust_forwarded_headers` is set.
+pub fn client_ip(req: &HttpRequest, trust_forwarded_headers: bool) -> Option<String> {
+    let forwarded_for = if trust_forwarded_headers {
+        forwarded_for(req.headers())
+    } else {
+        None
+    };
+    forwarded_for.or_else(|| {
+        req.peer_addr()
+            .map(|addr| addr.ip().to_string())
+    })
+}
+
+/// The right-most `X-Forwarded-For` entry, the one appended by the proxy in
+/// front of us: the entries to its left were sent by the client.
+fn forwarded_for(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_owned)
+}
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    email: Option<String>,
+}
+
+/// Reject requests exceeding the limits configured for the route they
+/// matched with `429 Too Many Requests`.
+pub async fn rate_limit(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let Some(limiter) = req
+        .app_data::<web::Data<RateLimiter>>()
+        .cloned()
+    else {
+        return Ok(next
+            .call(req)
+            .await?
+            .map_into_left_body());
+    };
+    let Some(limits) = req
+        .match_pattern()
+        .and_then(|route| {
+            limiter
+                .settings
+                .routes
+                .get(&route)
+                .map(|limits| (route, limits.clone()))
+        })
+    else {
+        return Ok(next
+            .call(req)
+            .await?
+            .map_into_left_body());
+    };
+    let (route, limits) = limits;
+
+    let mut buckets = Vec::new();
+    if let Some(limit) = limits.per_ip {
+        let ip = client_ip(
+            req.request(),
+            limiter
+                .settings
+                .trust_forwarded_headers,
+        );
+        if let Some(ip) = ip {
+            buckets.push((format!("{}:ip:{}", route, ip), limit));
+        }
+    }
+    if limits.per_email.is_some()
+        || limits
+            .per_email_domain
+            .is_some()
+    {
+        // Read the body to find out who the email is for, then put it back
+        // for the handler.
+        let body = req
+            .extract::<web::Bytes>()
+            .await?;
+        let email = serde_urlencoded::from_bytes::<EmailField>(&body)
+            .ok()
+            .and_then(|form| form.email)
+            .map(|email| email.trim().to_lowercase());
+        req.set_payload(body.into());
+        if let Some(email) = email {
+            if let Some(limit) = limits.per_email {
+                buckets.push((format!("{}:email:{}", route, email), limit));
+            }
+            if let (Some(limit), Some((_, domain))) =
+                (limits.per_email_domain, email.rsplit_once('@'))
+            {
+                buckets.push((format!("{}:domain:{}", route, domain), limit));
+            }
+        }
+    }
+
+    for (i, (key, limit)) in buckets.iter().enumerate() {
+        if let Err(RateLimited { retry_after }) = limiter
+            .take(key, *limit)
+            .await
+        {
+            tracing::info!(bucket = %key, "Rate limit exceeded.");
+            // A rejected request must not count against the other buckets.
+            for (key, limit) in &buckets[..i] {
+                limiter
+                    .refund(key, *limit)
+                    .await;
+            }
+            let retry_after = retry_after
+                .as_secs_f64()
+                .ceil()
+                .max(1.0) as u64;
+            let mut response = ProblemDetails::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited")
+                .detail(format!(
+                    "Too many attempts, try again in {} seconds.",
+                    retry_after
+                ))
+                .into_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.into());
+            return Ok(req
+                .into_response(response)
+                .map_into_right_body());
+        }
+    }
+
+    Ok(next
+        .call(req)
+        .await?
+        .map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::test::TestRequest;
+    use claims::{
+        assert_err,
+        assert_ok,
+    };
+    use hashlink::LruCache;
+
+    use super::{
+        client_ip,
+        refund_to_memory,
+        take_from_memory,
+        take_token,
+    };
+    use crate::configuration::TokenBucketSettings;
+
+    const LIMIT: TokenBucketSettings = TokenBucketSettings {
+        capacity: 2,
+        refill_per_minute: 6.0,
+    };
+
+    #[test]
+    fn a_full_bucket_lets_capacity_requests_through() {
+        let mut buckets = LruCache::new(100);
+
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        let rate_limited = assert_err!(take_from_memory(&mut buckets, "key", LIMIT));
+
+        // One token every 10 seconds.
+        assert!(rate_limited.retry_after > Duration::from_secs(9));
+        assert!(rate_limited.retry_after <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn buckets_are_independent() {
+        let mut buckets = LruCache::new(100);
+
+        assert_ok!(take_from_memory(&mut buckets, "a", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "a", LIMIT));
+
+        assert_ok!(take_from_memory(&mut buckets, "b", LIMIT));
+    }
+
+    #[test]
+    fn a_refunded_token_can_be_taken_again() {
+        let mut buckets = LruCache::new(100);
+
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        refund_to_memory(&mut buckets, "key", LIMIT);
+
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        assert_err!(take_from_memory(&mut buckets, "key", LIMIT));
+    }
+
+    #[test]
+    fn refunds_do_not_fill_beyond_capacity() {
+        let mut buckets = LruCache::new(100);
+
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        refund_to_memory(&mut buckets, "key", LIMIT);
+        refund_to_memory(&mut buckets, "key", LIMIT);
+
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "key", LIMIT));
+        assert_err!(take_from_memory(&mut buckets, "key", LIMIT));
+    }
+
+    #[test]
+    fn full_buckets_are_forgotten() {
+        let mut buckets = LruCache::new(100);
+        let refill_at_once = TokenBucketSettings {
+            capacity: 1,
+            refill_per_minute: f64::INFINITY,
+        };
+
+        assert_ok!(take_from_memory(&mut buckets, "a", refill_at_once));
+        assert_ok!(take_from_memory(&mut buckets, "b", LIMIT));
+
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets.contains_key("b"));
+    }
+
+    #[test]
+    fn the_least_recently_used_bucket_makes_room_for_new_ones() {
+        let mut buckets = LruCache::new(2);
+
+        assert_ok!(take_from_memory(&mut buckets, "a", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "b", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "a", LIMIT));
+        assert_ok!(take_from_memory(&mut buckets, "c", LIMIT));
+
+        assert_eq!(buckets.len(), 2);
+        assert!(!buckets.contains_key("b"));
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let (tokens, outcome) = take_token(0.0, Duration::from_secs(10), LIMIT);
+        assert_ok!(outcome);
+        assert!(tokens.abs() < 1e-9);
+
+        let (_, outcome) = take_token(0.0, Duration::from_secs(5), LIMIT);
+        let rate_limited = assert_err!(outcome);
+        assert_eq!(
+            rate_limited
+                .retry_after
+                .as_secs(),
+            5
+        );
+    }
+
+    #[test]
+    fn buckets_do_not_fill_beyond_their_capacity() {
+        let (tokens, outcome) = take_token(0.0, Duration::from_secs(3600), LIMIT);
+
+        assert_ok!(outcome);
+        assert_eq!(tokens, 1.0);
+    }
+
+    #[test]
+    fn forwarded_headers_are_ignored_unless_trusted() {
+        let req = TestRequest::default()
+            .peer_addr(
+                "10.0.0.1:1234"
+                    .parse()
+                    .unwrap(),
+            )
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .to_http_request();
+
+        assert_eq!(client_ip(&req, false).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_ip(&req, true).as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_client_is_the_right_most_forwarded_entry() {
+        // The client sent the first entry itself, the proxy appended the
+        // second one.
+        let req = TestRequest::default()
+            .peer_addr(
+                "10.0.0.1:1234"
+                    .parse()
+                    .unwrap(),
+            )
+            .insert_header(("X-Forwarded-For", "1.2.3.4, 203.0.113.7"))
+            .to_http_request();
+
+        assert_eq!(client_ip(&req, true).as_deref(), Some("203.0.113.7"));
+    }
+}
@@ -0,0 +1,113 @@
+//! src/repositories.rs
+//!
+//! Where route handlers store subscribers and their tokens, so that their
+//! logic can be exercised without a database.
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{
+    InvalidStatusTransition,
+    NewSubscriber,
+    SubscriptionStatus,
+};
+
+mod in_memory;
+mod postgres;
+
+pub use in_memory::{
+    InMemoryRepository,
+    StoredSubscriber,
+};
+pub use postgres::PostgresRepository;
+
+#[async_trait::async_trait]
+pub trait SubscriberRepository: Send + Sync {
+    /// Store a subscriber pending confirmation, along with the token of their
+    /// confirmation link: either both are stored or neither is.
+    async fn insert_pending(
+        &self,
+        new_subscriber: &NewSubscriber,
+        subscription_token: &str,
+    ) -> Result<Uuid, InsertSubscriberError>;
+}
+
+#[async_trait::async_trait]
+pub trait TokenRepository: Send + Sync {
+    /// Confirm the subscriber `subscription_token` was sent to, looking the
+    /// token up, checking it was issued less than `valid_for` ago and
+    /// changing their status in a single transaction.
+    ///
+    /// Returns the status they were in, or `None` if the token is unknown.
+    async fn confirm(
+        &self,
+        subscription_token: &str,
+        valid_for: Duration,
+    ) -> Result<Option<SubscriptionStatus>, ConfirmTokenError>;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InsertSubscriberError {
+    /// Emails are unique regardless of case.
+    #[error("There already is a subscriber with this email.")]
+    DuplicateEmail,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ChangeStatusError {
+    #[error(transparent)]
+    InvalidTransition(#[from] InvalidStatusTransition),
+    #[error("There is no subscriber with id {0}.")]
+    UnknownSubscriber(Uuid),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfirmTokenError {
+    #[error("The subscription token has expired.")]
+    ExpiredToken,
+    #[error(transparent)]
+    ChangeStatus(#[from] ChangeStatusError),
+}
+
+impl From<anyhow::Error> for ConfirmTokenError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::ChangeStatus(e.into())
+    }
+}
+
+/// The repositories handed to `startup::run`.
+#[derive(Clone)]
+pub struct Repositories {
+    pub subscribers: Arc<dyn SubscriberRepository>,
+    pub tokens: Arc<dyn TokenRepository>,
+}
+
+impl Repositories {
+    pub fn postgres(pool: PgPool) -> Self {
+        Self::from(Arc::new(PostgresRepository::new(pool)))
+    }
+
+    pub fn in_memory() -> Self {
+        Self::from(Arc::new(InMemoryRepository::default()))
+    }
+}
+
+impl<R> From<Arc<R>> for Repositories
+where
+    R: SubscriberRepository + TokenRepository + 'static,
+{
+    fn from(repository: Arc<R>) -> Self {
+        Self {
+            subscribers: repository.clone(),
+            tokens: repository,
+        }
+    }
+}
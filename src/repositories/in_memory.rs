@@ -0,0 +1,179 @@
+//! src/repositories/in_memory.rs
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{
+    DateTime,
+    Utc,
+};
+use uuid::Uuid;
+
+use super::{
+    ChangeStatusError,
+    ConfirmTokenError,
+    InsertSubscriberError,
+    SubscriberRepository,
+    TokenRepository,
+};
+use crate::domain::{
+    NewSubscriber,
+    SubscriptionStatus,
+};
+
+/// Keeps everything in memory, for tests that do not need Postgres.
+///
+/// It enforces the same constraints as our schema: emails are unique
+/// regardless of case and transitions are checked.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    subscribers: HashMap<Uuid, StoredSubscriber>,
+    tokens: HashMap<String, StoredToken>,
+}
+
+struct StoredToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+impl InMemoryRepository {
+    /// Every stored subscriber, oldest first.
+    pub fn subscribers(&self) -> Vec<StoredSubscriber> {
+        let mut subscribers: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .subscribers
+            .values()
+            .cloned()
+            .collect();
+        subscribers.sort_by_key(|subscriber| subscriber.subscribed_at);
+        subscribers
+    }
+
+    /// Move a subscriber to `next`, if `SubscriptionStatus::can_become`
+    /// allows it, returning the status they were in: for tests to set the
+    /// stage.
+    pub fn change_status(
+        &self,
+        subscriber_id: Uuid,
+        next: SubscriptionStatus,
+    ) -> Result<SubscriptionStatus, ChangeStatusError> {
+        self.state
+            .lock()
+            .unwrap()
+            .change_status(subscriber_id, next)
+    }
+}
+
+#[async_trait::async_trait]
+impl SubscriberRepository for InMemoryRepository {
+    async fn insert_pending(
+        &self,
+        new_subscriber: &NewSubscriber,
+        subscription_token: &str,
+    ) -> Result<Uuid, InsertSubscriberError> {
+        let mut state = self.state.lock().unwrap();
+        let email = new_subscriber.email.as_ref();
+        if state
+            .subscribers
+            .values()
+            .any(|subscriber| {
+                subscriber
+                    .email
+                    .to_lowercase()
+                    == email.to_lowercase()
+            })
+        {
+            return Err(InsertSubscriberError::DuplicateEmail);
+        }
+        if state
+            .tokens
+            .contains_key(subscription_token)
+        {
+            return Err(anyhow::anyhow!("The subscription token is already in use.").into());
+        }
+        let id = Uuid::new_v4();
+        state.subscribers.insert(
+            id,
+            StoredSubscriber {
+                id,
+                email: email.into(),
+                name: new_subscriber
+                    .name
+                    .as_ref()
+                    .into(),
+                status: SubscriptionStatus::PendingConfirmation,
+                subscribed_at: Utc::now(),
+            },
+        );
+        state.tokens.insert(
+            subscription_token.into(),
+            StoredToken {
+                subscriber_id: id,
+                created_at: Utc::now(),
+            },
+        );
+        Ok(id)
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenRepository for InMemoryRepository {
+    async fn confirm(
+        &self,
+        subscription_token: &str,
+        valid_for: Duration,
+    ) -> Result<Option<SubscriptionStatus>, ConfirmTokenError> {
+        let mut state = self.state.lock().unwrap();
+        let Some(token) = state
+            .tokens
+            .get(subscription_token)
+        else {
+            return Ok(None);
+        };
+        let age = (Utc::now() - token.created_at)
+            .to_std()
+            .unwrap_or_default();
+        if age >= valid_for {
+            return Err(ConfirmTokenError::ExpiredToken);
+        }
+        let subscriber_id = token.subscriber_id;
+        Ok(Some(state.change_status(
+            subscriber_id,
+            SubscriptionStatus::Confirmed,
+        )?))
+    }
+}
+
+impl State {
+    fn change_status(
+        &mut self,
+        subscriber_id: Uuid,
+        next: SubscriptionStatus,
+    ) -> Result<SubscriptionStatus, ChangeStatusError> {
+        let subscriber = self
+            .subscribers
+            .get_mut(&subscriber_id)
+            .ok_or(ChangeStatusError::UnknownSubscriber(subscriber_id))?;
+        let previous = subscriber.status;
+        subscriber.status = previous.transition_to(next)?;
+        Ok(previous)
+    }
+}
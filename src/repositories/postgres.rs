@@ -0,0 +1,200 @@
+//! src/repositories/postgres.rs
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{
+    Executor,
+    PgPool,
+    Postgres,
+    Transaction,
+};
+use uuid::Uuid;
+
+use super::{
+    ChangeStatusError,
+    ConfirmTokenError,
+    InsertSubscriberError,
+    SubscriberRepository,
+    TokenRepository,
+};
+use crate::{
+    domain::{
+        NewSubscriber,
+        SubscriptionStatus,
+    },
+    error::error_chain_fmt,
+    metrics::begin_transaction,
+};
+
+pub struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl SubscriberRepository for PostgresRepository {
+    async fn insert_pending(
+        &self,
+        new_subscriber: &NewSubscriber,
+        subscription_token: &str,
+    ) -> Result<Uuid, InsertSubscriberError> {
+        let mut transaction = begin_transaction(&self.pool)
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+
+        let subscriber_id = insert_subscriber(&mut transaction, new_subscriber)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(ref e)
+                    if e.constraint() == Some("subscriptions_lower_email_key") =>
+                {
+                    InsertSubscriberError::DuplicateEmail
+                }
+                e => anyhow::Error::new(e)
+                    .context("Failed to insert new subscriber in the database.")
+                    .into(),
+            })?;
+
+        store_token(&mut transaction, subscriber_id, subscription_token)
+            .await
+            .context("Failed to store the confirmation token for a new subscriber.")?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store a new subscriber.")?;
+        Ok(subscriber_id)
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenRepository for PostgresRepository {
+    #[tracing::instrument(
+        name = "Confirm the subscriber of a token",
+        skip(self, subscription_token)
+    )]
+    async fn confirm(
+        &self,
+        subscription_token: &str,
+        valid_for: Duration,
+    ) -> Result<Option<SubscriptionStatus>, ConfirmTokenError> {
+        let mut transaction = begin_transaction(&self.pool)
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let Some(token) = sqlx::query!(
+            r#"SELECT subscriber_id, created_at <= now() - make_interval(secs => $2) AS "expired!"
+            FROM subscription_tokens WHERE subscription_token = $1"#,
+            subscription_token,
+            valid_for.as_secs_f64(),
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch the subscriber of a subscription token.")?
+        else {
+            return Ok(None);
+        };
+        if token.expired {
+            return Err(ConfirmTokenError::ExpiredToken);
+        }
+        let previous = change_status(
+            &mut transaction,
+            token.subscriber_id,
+            SubscriptionStatus::Confirmed,
+        )
+        .await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the confirmation.")?;
+        Ok(Some(previous))
+    }
+}
+
+/// Move a subscriber to `next` within `transaction`, returning the status
+/// they were in.
+async fn change_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    next: SubscriptionStatus,
+) -> Result<SubscriptionStatus, ChangeStatusError> {
+    // Lock the row, so that the status cannot change under our feet.
+    let current = sqlx::query_scalar!(
+        r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch the status of the subscriber.")?
+    .ok_or(ChangeStatusError::UnknownSubscriber(subscriber_id))?;
+    let next = current.transition_to(next)?;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE id = $1"#,
+        subscriber_id,
+        next as SubscriptionStatus,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update the status of the subscriber.")?;
+    Ok(current)
+}
+
+#[tracing::instrument(
+    name = "Saving new subscriber details in the database",
+    skip(new_subscriber, transaction)
+)]
+async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Uuid, sqlx::Error> {
+    let subscriber_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, $5)"#,
+        subscriber_id,
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+    );
+    transaction
+        .execute(query)
+        .await?;
+    Ok(subscriber_id)
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), StoreTokenError> {
+    let query = sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)"#,
+        subscription_token,
+        subscriber_id
+    );
+
+    transaction
+        .execute(query)
+        .await
+        .map_err(StoreTokenError)?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+#[error("A database error was encountered while trying to store a subscription token.")]
+pub struct StoreTokenError(#[source] sqlx::Error);
+
+impl std::fmt::Debug for StoreTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
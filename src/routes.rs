@@ -1,7 +1,14 @@
+mod confirmation_page;
+mod docs;
 pub mod health_check;
 pub mod subscriptions;
 
 mod newsletters;
+pub use confirmation_page::{
+    ConfirmationBody,
+    ConfirmationOutcome,
+};
+pub use docs::*;
 pub use newsletters::*;
 
 mod subscriptions_confirm;
@@ -0,0 +1,226 @@
+//! src/routes/confirmation_page.rs
+use actix_web::{
+    http::{
+        header::{
+            self,
+            Accept,
+            ContentType,
+            Header,
+        },
+        StatusCode,
+    },
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::configuration::{
+    ConfirmationPageSettings,
+    PageMessage,
+};
+
+const TEMPLATE: &str = include_str!("confirmation_page.html");
+
+/// What became of an attempt to confirm a subscription.
+#[derive(serde::Serialize, utoipa::ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmationOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    /// No subscription was sent this token.
+    InvalidToken,
+    /// The token was sent too long ago.
+    ExpiredToken,
+    /// The subscription cannot be confirmed anymore, e.g. because the
+    /// subscriber unsubscribed since.
+    Refused,
+    Error,
+}
+
+/// The body returned to clients asking for JSON.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ConfirmationBody<'a> {
+    status: ConfirmationOutcome,
+    /// The message of the matching page, as configured.
+    message: &'a str,
+}
+
+impl ConfirmationOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Confirmed => "confirmed",
+            Self::AlreadyConfirmed => "already_confirmed",
+            Self::InvalidToken => "invalid_token",
+            Self::ExpiredToken => "expired_token",
+            Self::Refused => "refused",
+            Self::Error => "error",
+        }
+    }
+
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            Self::Confirmed | Self::AlreadyConfirmed => StatusCode::OK,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::Refused => StatusCode::CONFLICT,
+            Self::Error => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn page(self, settings: &ConfirmationPageSettings) -> &PageMessage {
+        match self {
+            Self::Confirmed => &settings.confirmed,
+            Self::AlreadyConfirmed => &settings.already_confirmed,
+            Self::InvalidToken | Self::Refused => &settings.invalid_token,
+            Self::ExpiredToken => &settings.expired_token,
+            Self::Error => &settings.error,
+        }
+    }
+}
+
+/// Answer API clients asking for JSON with a JSON body. Browsers are
+/// redirected if a redirect URL is configured, and get an HTML page
+/// otherwise.
+pub fn respond(
+    req: &HttpRequest,
+    settings: &ConfirmationPageSettings,
+    outcome: ConfirmationOutcome,
+) -> HttpResponse {
+    let page = outcome.page(settings);
+    if prefers_json(req) {
+        return HttpResponse::build(outcome.status_code()).json(ConfirmationBody {
+            status: outcome,
+            message: &page.message,
+        });
+    }
+    if let Some(redirect_url) = &settings.redirect_url {
+        // Validated on startup.
+        if let Ok(mut location) = reqwest::Url::parse(redirect_url) {
+            location
+                .query_pairs_mut()
+                .append_pair("status", outcome.as_str());
+            return HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, location.as_str()))
+                .finish();
+        }
+    }
+    HttpResponse::build(outcome.status_code())
+        .content_type(ContentType::html())
+        .body(render(settings, page))
+}
+
+/// Whether the client ranks JSON above HTML. Clients without preference
+/// (e.g. `Accept: */*`) get HTML, as they are most likely browsers.
+pub fn prefers_json(req: &HttpRequest) -> bool {
+    Accept::parse(req)
+        .map(|accept| accept.ranked())
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|mime| match mime.essence_str() {
+            "application/json" | "application/problem+json" => Some(true),
+            "text/html" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+fn render(settings: &ConfirmationPageSettings, page: &PageMessage) -> String {
+    // A single pass, so that placeholders in the configured texts are left
+    // alone.
+    let mut html = String::with_capacity(TEMPLATE.len());
+    let mut rest = TEMPLATE;
+    while let Some((before, after)) = rest.split_once("{{") {
+        let (placeholder, after) = after
+            .split_once("}}")
+            .expect("Unterminated placeholder in the confirmation page template.");
+        html.push_str(before);
+        match placeholder {
+            "accent_color" => html.push_str(&escape(&settings.accent_color)),
+            "brand_name" => html.push_str(&escape(&settings.brand_name)),
+            "title" => html.push_str(&escape(&page.title)),
+            "message" => html.push_str(&escape(&page.message)),
+            "logo" => {
+                if let Some(logo_url) = &settings.logo_url {
+                    html.push_str(&format!(r#"<img src="{}" alt="">"#, escape(logo_url)));
+                }
+            }
+            placeholder => panic!(
+                "Unknown placeholder `{}` in the confirmation page template.",
+                placeholder
+            ),
+        }
+        rest = after;
+    }
+    html.push_str(rest);
+    html
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::{
+        prefers_json,
+        render,
+    };
+    use crate::configuration::{
+        ConfirmationPageSettings,
+        PageMessage,
+    };
+
+    #[test]
+    fn browsers_get_html_and_api_clients_json() {
+        let prefers_json = |accept: Option<&str>| {
+            let mut req = TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.insert_header(("Accept", accept));
+            }
+            prefers_json(&req.to_http_request())
+        };
+
+        assert!(!prefers_json(None));
+        assert!(!prefers_json(Some("*/*")));
+        assert!(!prefers_json(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        )));
+        assert!(prefers_json(Some("application/json")));
+        assert!(prefers_json(Some("text/html;q=0.5, application/json")));
+    }
+
+    #[test]
+    fn configured_texts_are_escaped() {
+        let settings = ConfirmationPageSettings {
+            brand_name: "Ursula's <b>zine</b>".into(),
+            logo_url: Some("https://example.com/logo.png?a=1&b=\"2\"".into()),
+            ..Default::default()
+        };
+        let page = PageMessage {
+            title: "{{message}}".into(),
+            message: "<script>alert(1)</script>".into(),
+        };
+
+        let html = render(&settings, &page);
+
+        assert!(html.contains("Ursula&#39;s &lt;b&gt;zine&lt;/b&gt;"));
+        assert!(html.contains(
+            r#"<img src="https://example.com/logo.png?a=1&amp;b=&quot;2&quot;" alt="">"#
+        ));
+        assert!(html.contains("<h1>{{message}}</h1>"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+}
@@ -0,0 +1,36 @@
+use actix_web::{
+    http::header::ContentType,
+    HttpResponse,
+};
+use utoipa::OpenApi;
+
+use crate::openapi::ApiDoc;
+
+/// Serve the OpenAPI document generated from our route handlers.
+pub async fn openapi_spec() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Serve a Redoc UI rendering `/openapi.json`.
+///
+/// Redoc is loaded from its CDN at a pinned version, so that a new release
+/// cannot change what runs on the page without us bumping it.
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(DOCS_PAGE)
+}
+
+const DOCS_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>zero2prod API</title>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/v2.1.5/bundles/redoc.standalone.js" crossorigin="anonymous"></script>
+  </body>
+</html>
+"#;
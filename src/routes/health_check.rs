@@ -1,5 +1,237 @@
-use actix_web::HttpResponse;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
 
+use actix_web::{
+    web,
+    HttpResponse,
+};
+use sqlx::PgPool;
+
+use crate::{
+    configuration::HealthCheckSettings,
+    email_client::EmailClient,
+    migrations::MIGRATOR,
+};
+
+/// Check that the application is up and able to serve requests.
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "health",
+    responses((status = 200, description = "The application is running."))
+)]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// The outcome of probing one of the dependencies of the application.
+#[derive(serde::Serialize, utoipa::ToSchema, Clone, Debug)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    /// How long the probe took.
+    pub latency_ms: u64,
+    /// Why the component is considered down.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ComponentHealth {
+    fn new(started_at: Instant, outcome: Result<(), String>) -> Self {
+        let latency_ms = started_at
+            .elapsed()
+            .as_millis() as u64;
+        match outcome {
+            Ok(()) => Self {
+                status: HealthStatus::Up,
+                latency_ms,
+                error: None,
+            },
+            Err(error) => Self {
+                status: HealthStatus::Down,
+                latency_ms,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema, Debug)]
+pub struct HealthReport {
+    /// `up` if, and only if, every component is up.
+    pub status: HealthStatus,
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+/// The last outcome of probing the email provider.
+///
+/// The provider is a third party: we do not want every readiness probe to
+/// translate into a request to their API.
+pub struct EmailProviderHealthCache {
+    ttl: Duration,
+    last_probe: Mutex<Option<(Instant, ComponentHealth)>>,
+}
+
+impl EmailProviderHealthCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            last_probe: Mutex::new(None),
+        }
+    }
+
+    fn get(&self) -> Option<ComponentHealth> {
+        let last_probe = self
+            .last_probe
+            .lock()
+            .unwrap();
+        last_probe
+            .as_ref()
+            .filter(|(probed_at, _)| probed_at.elapsed() < self.ttl)
+            .map(|(_, health)| health.clone())
+    }
+
+    fn set(&self, health: ComponentHealth) {
+        *self
+            .last_probe
+            .lock()
+            .unwrap() = Some((Instant::now(), health));
+    }
+}
+
+/// Check that the process is alive.
+///
+/// It does not look at any dependency: a failure means the process must be
+/// restarted.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The process is alive.", body = HealthReport))
+)]
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(HealthReport {
+        status: HealthStatus::Up,
+        components: BTreeMap::new(),
+    })
+}
+
+/// Check that the application can serve traffic, i.e. that its dependencies
+/// are available.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every component is up.", body = HealthReport),
+        (status = 503, description = "At least one component is down.", body = HealthReport),
+    )
+)]
+#[tracing::instrument(
+    name = "Check readiness",
+    skip(pool, email_client, settings, email_provider_cache)
+)]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthCheckSettings>,
+    email_provider_cache: web::Data<EmailProviderHealthCache>,
+) -> HttpResponse {
+    let timeout = settings.database_timeout();
+    let mut components = BTreeMap::new();
+    components.insert("database", check_database(&pool, timeout).await);
+    components.insert("migrations", check_migrations(&pool, timeout).await);
+    if settings.check_email_provider {
+        let email_provider = match email_provider_cache.get() {
+            Some(health) => health,
+            None => {
+                let health = check_email_provider(&email_client).await;
+                email_provider_cache.set(health.clone());
+                health
+            }
+        };
+        components.insert("email_provider", email_provider);
+    }
+
+    let status = if components
+        .values()
+        .all(|c| c.status == HealthStatus::Up)
+    {
+        HealthStatus::Up
+    } else {
+        HealthStatus::Down
+    };
+    let report = HealthReport { status, components };
+    match status {
+        HealthStatus::Up => HttpResponse::Ok().json(report),
+        HealthStatus::Down => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+#[tracing::instrument(name = "Check the database", skip(pool))]
+async fn check_database(pool: &PgPool, timeout: Duration) -> ComponentHealth {
+    let started_at = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "The database is unavailable.");
+            Err("The database could not be queried.".into())
+        }
+        Err(_) => Err("The database did not answer in time.".into()),
+    };
+    ComponentHealth::new(started_at, outcome)
+}
+
+/// Check that every migration known to this binary has been applied.
+#[tracing::instrument(name = "Check the database migrations", skip(pool))]
+async fn check_migrations(pool: &PgPool, timeout: Duration) -> ComponentHealth {
+    let started_at = Instant::now();
+    let query = sqlx::query_scalar::<_, i64>(
+        "SELECT version FROM _sqlx_migrations WHERE success ORDER BY version",
+    )
+    .fetch_all(pool);
+    let outcome = match tokio::time::timeout(timeout, query).await {
+        Ok(Ok(applied)) => {
+            let pending = MIGRATOR
+                .iter()
+                .filter(|m| !applied.contains(&m.version))
+                .count();
+            if pending == 0 {
+                Ok(())
+            } else {
+                Err(format!("{} migration(s) have not been applied.", pending))
+            }
+        }
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Failed to list the applied migrations.");
+            Err("The applied migrations could not be listed.".into())
+        }
+        Err(_) => Err("The database did not answer in time.".into()),
+    };
+    ComponentHealth::new(started_at, outcome)
+}
+
+#[tracing::instrument(name = "Check the email provider", skip(email_client))]
+async fn check_email_provider(email_client: &EmailClient) -> ComponentHealth {
+    let started_at = Instant::now();
+    let outcome = email_client
+        .ping()
+        .await
+        .map_err(|e| {
+            tracing::warn!(error.cause_chain = ?e, "The email provider is unreachable.");
+            "The email provider could not be reached.".to_string()
+        });
+    ComponentHealth::new(started_at, outcome)
+}
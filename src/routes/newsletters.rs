@@ -1,4 +1,12 @@
 use actix_web::HttpResponse;
+
+/// Publish a newsletter issue to all confirmed subscribers.
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    responses((status = 200, description = "The newsletter issue was published."))
+)]
 // Dummy implementation
 pub async fn publish_newsletter() -> HttpResponse {
     HttpResponse::Ok().finish()
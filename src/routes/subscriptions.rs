@@ -1,31 +1,93 @@
-use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{
+    http::StatusCode,
+    web,
+    HttpRequest,
+    HttpResponse,
+    ResponseError,
+};
 use anyhow::Context;
-
 use chrono::Utc;
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use sqlx::{Executor, PgPool, Postgres, Transaction};
-use uuid::Uuid;
+use rand::{
+    distributions::Alphanumeric,
+    thread_rng,
+    Rng,
+};
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    bot_protection::{
+        BotCheckError,
+        BotProtection,
+    },
+    domain::{
+        NewSubscriber,
+        NewSubscriberError,
+        SubscriberEmail,
+        SubscriberName,
+    },
+    email_checks::EmailChecks,
     email_client::EmailClient,
+    error::{
+        error_chain_fmt,
+        problem_response,
+        Problem,
+        ProblemDetails,
+    },
+    metrics,
+    repositories::{
+        InsertSubscriberError,
+        SubscriberRepository,
+    },
     startup::ApplicationBaseUrl,
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct FormData {
+    /// The name the subscriber wants to be addressed with.
+    #[schema(example = "Ursula Le Guin", max_length = 256)]
     name: String,
+    #[schema(example = "ursula_le_guin@gmail.com", format = "email")]
     email: String,
+    /// A honeypot: hidden from people by the form, it must be left empty.
+    #[serde(default)]
+    #[schema(example = "")]
+    website: Option<String>,
+    /// When the form was rendered, as a Unix timestamp. Required if a
+    /// minimum time to submit is configured.
+    #[serde(default)]
+    #[schema(example = 1760774400)]
+    rendered_at: Option<i64>,
+    /// The solution produced by the CAPTCHA widget, if one is configured.
+    /// The field names used by the hCaptcha, Turnstile and reCAPTCHA widgets
+    /// are accepted too.
+    #[serde(
+        default,
+        alias = "h-captcha-response",
+        alias = "cf-turnstile-response",
+        alias = "g-recaptcha-response"
+    )]
+    captcha_response: Option<String>,
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error(transparent)]
+    ValidationError(#[from] NewSubscriberError),
+    #[error(transparent)]
+    BotDetected(#[from] BotCheckError),
+    #[error("There already is a subscription for this email.")]
+    AlreadySubscribed,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
+
+impl From<InsertSubscriberError> for SubscribeError {
+    fn from(e: InsertSubscriberError) -> Self {
+        match e {
+            InsertSubscriberError::DuplicateEmail => Self::AlreadySubscribed,
+            InsertSubscriberError::Unexpected(e) => Self::UnexpectedError(e),
+        }
+    }
+}
 impl std::fmt::Debug for SubscribeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -34,47 +96,113 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ValidationError(_) | SubscribeError::BotDetected(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            SubscribeError::AlreadySubscribed => StatusCode::CONFLICT,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+impl Problem for SubscribeError {
+    fn code(&self) -> &'static str {
+        match self {
+            SubscribeError::ValidationError(_) => "validation_failed",
+            SubscribeError::BotDetected(e) => e.code(),
+            SubscribeError::AlreadySubscribed => "already_subscribed",
+            SubscribeError::UnexpectedError(_) => "internal_server_error",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        match self {
+            SubscribeError::ValidationError(_)
+            | SubscribeError::BotDetected(_)
+            | SubscribeError::AlreadySubscribed => Some(self.to_string()),
+            SubscribeError::UnexpectedError(_) => None,
+        }
+    }
+
+    fn extensions(&self) -> serde_json::Map<String, serde_json::Value> {
+        match self {
+            // Validation failures are the client's fault: tell them which field
+            // is wrong in a machine-readable way so that they can fix (and
+            // localize) it.
+            SubscribeError::ValidationError(e) => match serde_json::to_value(e) {
+                Ok(serde_json::Value::Object(members)) => members,
+                _ => serde_json::Map::new(),
+            },
+            _ => serde_json::Map::new(),
+        }
+    }
 }
 
+/// Register a new subscriber and send them a confirmation email.
+///
+/// The subscription stays pending until the link in the email is followed.
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content = FormData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The subscriber was registered and a confirmation email was sent."),
+        (status = 400, description = "The submitted form is invalid (`validation_failed`, with the offending `field` and its `error`) or was taken for a bot's (the `code` of a `BotCheckError`).",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "There already is a subscription for this email, whatever its case (`already_subscribed`).",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many attempts from this client, for this email or for its domain.",
+            body = ProblemDetails, content_type = "application/problem+json",
+            headers(("Retry-After" = u64, description = "How many seconds to wait before trying again."))),
+        (status = 500, description = "The subscriber could not be stored or the email could not be sent.",
+            body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 #[tracing::instrument(
-    name = "Adding a new subscriber", skip(form, pool, email_client, base_url),
+    name = "Adding a new subscriber", skip(form, req, subscribers, email_client, base_url, bot_protection, email_checks),
     fields(
         subscriber_email = %form.email, subscriber_name = %form.name
     ) )]
 pub async fn subscribe(
     form: web::Form<FormData>,
-    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    subscribers: web::Data<dyn SubscriberRepository>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtection>,
+    email_checks: web::Data<EmailChecks>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form
-        .0
-        .try_into()
-        .map_err(SubscribeError::ValidationError)?;
-
-    let mut transaction = pool
-        .begin()
+    // Bots are turned away before they cost us a row or an email.
+    if bot_protection.honeypot_filled(form.website.as_deref()) {
+        tracing::info!("The honeypot was filled in: the submission was ignored.");
+        metrics::record_honeypot_submission();
+        return Ok(HttpResponse::Ok().finish());
+    }
+    bot_protection.check_form(form.rendered_at, Utc::now())?;
+    bot_protection
+        .check_captcha(
+            form.captcha_response
+                .as_deref(),
+            &req,
+        )
         .await
-        .context("Failed to acquire a Postgres connection from the pool")?;
+        .context("Failed to verify the CAPTCHA solution.")??;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let mut new_subscriber: NewSubscriber = form.0.try_into()?;
+    new_subscriber.email = email_checks
+        .check(new_subscriber.email)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .map_err(NewSubscriberError::Email)?;
 
     let subscription_token = generate_subscription_token();
-
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
-
-    transaction
-        .commit()
-        .await
-        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    subscribers
+        .insert_pending(&new_subscriber, &subscription_token)
+        .await?;
 
     send_confirmation_email(
         &email_client,
@@ -88,64 +216,6 @@ pub async fn subscribe(
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(
-    name = "Store subscription token in the database",
-    skip(subscription_token, transaction)
-)]
-pub async fn store_token(
-    transaction: &mut Transaction<'_, Postgres>,
-    subscriber_id: Uuid,
-    subscription_token: &str,
-) -> Result<(), StoreTokenError> {
-    let query = sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)"#,
-        subscription_token,
-        subscriber_id
-    );
-
-    transaction
-        .execute(query)
-        .await
-        .map_err(|e| StoreTokenError(e))?;
-    Ok(())
-}
-
-pub struct StoreTokenError(sqlx::Error);
-impl std::fmt::Display for StoreTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "A database error was encountered while trying to store a subscription token."
-        )
-    }
-}
-
-impl std::fmt::Debug for StoreTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
-impl std::error::Error for StoreTokenError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        // The compiler transparently casts `&sqlx::Error` into a `&dyn Error`
-        Some(&self.0)
-    }
-}
-
-fn error_chain_fmt(
-    e: &impl std::error::Error,
-    f: &mut std::fmt::Formatter<'_>,
-) -> std::fmt::Result {
-    writeln!(f, "{}\n", e)?;
-    let mut current = e.source();
-    while let Some(cause) = current {
-        writeln!(f, "Caused by:\n\t{}", cause)?;
-        current = cause.source();
-    }
-    Ok(())
-}
-
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
     skip(email_client, new_subscriber, base_url, subscription_token)
@@ -178,7 +248,7 @@ pub async fn send_confirmation_email(
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = NewSubscriberError;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(value.name)?;
@@ -187,34 +257,6 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
-#[tracing::instrument(
-    name = "Saving new subscriber details in the database",
-    skip(new_subscriber, transaction)
-)]
-pub async fn insert_subscriber(
-    transaction: &mut Transaction<'_, Postgres>,
-    new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    let query = sqlx::query!(
-        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, 'pending_confirmation')"#,
-        subscriber_id,
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
-        Utc::now()
-    );
-    transaction
-        .execute(query)
-        .await
-        .map_err(|e| {
-            e
-            // Using the `?` operator to return early
-            // if the function failed, returning a sqlx::Error
-            // We will talk about error handling in depth later!
-        })?;
-    Ok(subscriber_id)
-}
-
 fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
@@ -222,3 +264,144 @@ fn generate_subscription_token() -> String {
         .take(25)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+
+    use actix_web::{
+        http::StatusCode,
+        test,
+        web::{
+            self,
+            Data,
+        },
+        App,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{
+            method,
+            path,
+        },
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::subscribe;
+    use crate::{
+        bot_protection::BotProtection,
+        configuration::{
+            BotProtectionSettings,
+            SubscriberEmailSettings,
+        },
+        domain::{
+            SubscriberEmail,
+            SubscriptionStatus,
+        },
+        email_checks::EmailChecks,
+        email_client::EmailClient,
+        repositories::{
+            InMemoryRepository,
+            SubscriberRepository,
+        },
+        startup::ApplicationBaseUrl,
+    };
+
+    async fn post_subscription(
+        repository: Arc<InMemoryRepository>,
+        email_server: &MockServer,
+        body: &'static str,
+    ) -> StatusCode {
+        let email_client = EmailClient::new(
+            email_server.uri(),
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            Secret::new("token".into()),
+            Duration::from_millis(200),
+        );
+        let app = test::init_service(
+            App::new()
+                .route("/subscriptions", web::post().to(subscribe))
+                .app_data(Data::from(repository as Arc<dyn SubscriberRepository>))
+                .app_data(Data::new(email_client))
+                .app_data(Data::new(ApplicationBaseUrl("http://127.0.0.1".into())))
+                .app_data(Data::new(
+                    BotProtection::new(&BotProtectionSettings::default(), false).unwrap(),
+                ))
+                .app_data(Data::new(EmailChecks::new(
+                    &SubscriberEmailSettings::default(),
+                ))),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/subscriptions")
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .set_payload(body)
+            .to_request();
+        test::call_service(&app, request)
+            .await
+            .status()
+    }
+
+    #[actix_web::test]
+    async fn subscribe_stores_a_pending_subscriber_and_sends_a_confirmation_email() {
+        // Arrange
+        let repository = Arc::new(InMemoryRepository::default());
+        let email_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&email_server)
+            .await;
+
+        // Act
+        let status = post_subscription(
+            repository.clone(),
+            &email_server,
+            "name=le%20guin&email=Ursula_Le_Guin%40Example.com",
+        )
+        .await;
+
+        // Assert
+        assert_eq!(status, StatusCode::OK);
+        let subscribers = repository.subscribers();
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(subscribers[0].email, "Ursula_Le_Guin@example.com");
+        assert_eq!(subscribers[0].name, "le guin");
+        assert_eq!(
+            subscribers[0].status,
+            SubscriptionStatus::PendingConfirmation
+        );
+    }
+
+    #[actix_web::test]
+    async fn subscribe_stores_nothing_if_the_form_is_invalid() {
+        // Arrange
+        let repository = Arc::new(InMemoryRepository::default());
+        let email_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&email_server)
+            .await;
+
+        // Act
+        let status = post_subscription(
+            repository.clone(),
+            &email_server,
+            "name=le%20guin&email=not-an-email",
+        )
+        .await;
+
+        // Assert
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(repository
+            .subscribers()
+            .is_empty());
+    }
+}
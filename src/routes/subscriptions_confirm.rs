@@ -1,60 +1,528 @@
 //! src/routes/subscriptions_confirm.rs
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
-use uuid::Uuid;
+use std::time::Duration;
 
-#[derive(serde::Deserialize)]
+use actix_web::{
+    error::InternalError,
+    http::StatusCode,
+    web,
+    HttpRequest,
+    HttpResponse,
+    ResponseError,
+};
+
+use super::confirmation_page::{
+    prefers_json,
+    respond,
+    ConfirmationBody,
+    ConfirmationOutcome,
+};
+use crate::{
+    configuration::ConfirmationPageSettings,
+    domain::{
+        InvalidStatusTransition,
+        SubscriptionStatus,
+    },
+    error::{
+        error_chain_fmt,
+        problem_response,
+        Problem,
+        ProblemDetails,
+    },
+    repositories::{
+        ChangeStatusError,
+        ConfirmTokenError,
+        TokenRepository,
+    },
+    startup::ConfirmationLinkValidity,
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
+    /// The token embedded in the link of the confirmation email.
     subscription_token: String,
 }
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    match id {
-        // Non-existing token!
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id)
-                .await
-                .is_err()
-            {
-                return HttpResponse::InternalServerError().finish();
+
+/// Confirm a pending subscription.
+///
+/// Browsers get an HTML page, or are redirected if
+/// `confirmation_page.redirect_url` is set; clients asking for
+/// `application/json` get a JSON body, or problem details if the
+/// subscription was not confirmed.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscription was confirmed, now or before.", body = ConfirmationBody),
+        (status = 303, description = "The outcome is handed to the configured redirect URL, in the `status` query parameter.",
+            headers(("Location" = String, description = "The configured redirect URL."))),
+        (status = 400, description = "The subscription token is missing.",
+            body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "The subscription token is unknown (`invalid_token`).",
+            content((ProblemDetails = "application/problem+json"), (String = "text/html"))),
+        (status = 409, description = "The subscription can no longer be confirmed (`invalid_status_transition`), e.g. because the subscriber unsubscribed.",
+            content((ProblemDetails = "application/problem+json"), (String = "text/html"))),
+        (status = 410, description = "The subscription token was issued more than `application.confirmation_link_validity_hours` ago (`expired_token`).",
+            content((ProblemDetails = "application/problem+json"), (String = "text/html"))),
+        (status = 500, description = "The subscription could not be confirmed.",
+            content((ProblemDetails = "application/problem+json"), (String = "text/html"))),
+    )
+)]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, req, tokens, validity, page_settings)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    req: HttpRequest,
+    tokens: web::Data<dyn TokenRepository>,
+    validity: web::Data<ConfirmationLinkValidity>,
+    page_settings: web::Data<ConfirmationPageSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match confirm_subscriber(&parameters.subscription_token, &**tokens, validity.0).await {
+        Ok(outcome) => Ok(respond(&req, &page_settings, outcome)),
+        Err(e) if prefers_json(&req) => Err(e.into()),
+        Err(e) => {
+            // Keep the error around for our logs, but answer with the page.
+            let response = respond(&req, &page_settings, e.outcome());
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("The subscription token has expired.")]
+    ExpiredToken,
+    /// The subscription cannot be confirmed anymore, e.g. because the
+    /// subscriber unsubscribed since the token was sent.
+    #[error("The subscription can no longer be confirmed.")]
+    InvalidTransition(#[source] InvalidStatusTransition),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        self.outcome().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+impl Problem for ConfirmError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownToken => "invalid_token",
+            Self::ExpiredToken => "expired_token",
+            Self::InvalidTransition(_) => "invalid_status_transition",
+            Self::UnexpectedError(_) => "internal_server_error",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        match self {
+            Self::UnknownToken | Self::ExpiredToken | Self::InvalidTransition(_) => {
+                Some(self.to_string())
             }
-            HttpResponse::Ok().finish()
+            Self::UnexpectedError(_) => None,
         }
     }
 }
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
-}
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+
+impl ConfirmError {
+    fn outcome(&self) -> ConfirmationOutcome {
+        match self {
+            Self::UnknownToken => ConfirmationOutcome::InvalidToken,
+            Self::ExpiredToken => ConfirmationOutcome::ExpiredToken,
+            Self::InvalidTransition(_) => ConfirmationOutcome::Refused,
+            Self::UnexpectedError(_) => ConfirmationOutcome::Error,
+        }
+    }
+}
+
+impl From<ChangeStatusError> for ConfirmError {
+    fn from(e: ChangeStatusError) -> Self {
+        match e {
+            ChangeStatusError::InvalidTransition(e) => Self::InvalidTransition(e),
+            ChangeStatusError::Unexpected(e) => Self::UnexpectedError(e),
+            e @ ChangeStatusError::UnknownSubscriber(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl From<ConfirmTokenError> for ConfirmError {
+    fn from(e: ConfirmTokenError) -> Self {
+        match e {
+            ConfirmTokenError::ExpiredToken => Self::ExpiredToken,
+            ConfirmTokenError::ChangeStatus(e) => e.into(),
+        }
+    }
+}
+
+async fn confirm_subscriber(
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
-        subscription_token,
-    )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
+    tokens: &dyn TokenRepository,
+    valid_for: Duration,
+) -> Result<ConfirmationOutcome, ConfirmError> {
+    let previous = tokens
+        .confirm(subscription_token, valid_for)
+        .await?
+        .ok_or(ConfirmError::UnknownToken)?;
+    Ok(match previous {
+        SubscriptionStatus::Confirmed => ConfirmationOutcome::AlreadyConfirmed,
+        _ => ConfirmationOutcome::Confirmed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+
+    use actix_web::{
+        dev::ServiceResponse,
+        http::{
+            header,
+            StatusCode,
+        },
+        test,
+        web::{
+            self,
+            Data,
+        },
+        App,
+        ResponseError,
+    };
+    use uuid::Uuid;
+
+    use super::{
+        confirm,
+        confirm_subscriber,
+        ConfirmError,
+    };
+    use crate::{
+        configuration::{
+            ConfirmationPageSettings,
+            PageMessage,
+        },
+        domain::{
+            NewSubscriber,
+            SubscriberEmail,
+            SubscriberName,
+            SubscriptionStatus,
+        },
+        error::Problem,
+        repositories::{
+            ChangeStatusError,
+            ConfirmTokenError,
+            InMemoryRepository,
+            SubscriberRepository,
+            TokenRepository,
+        },
+        routes::confirmation_page::ConfirmationOutcome,
+        startup::ConfirmationLinkValidity,
+    };
+
+    const VALID_FOR: Duration = Duration::from_secs(60 * 60);
+
+    /// A repository whose database is unreachable.
+    struct FailingRepository;
+
+    #[async_trait::async_trait]
+    impl TokenRepository for FailingRepository {
+        async fn confirm(
+            &self,
+            _subscription_token: &str,
+            _valid_for: Duration,
+        ) -> Result<Option<SubscriptionStatus>, ConfirmTokenError> {
+            Err(anyhow::anyhow!("The database is unreachable.")
+                .context("Failed to fetch the subscriber of a subscription token.")
+                .into())
+        }
+    }
+
+    /// A repository holding a single pending subscriber, whose confirmation
+    /// token is `token`.
+    async fn repository_with_pending_subscriber() -> Arc<InMemoryRepository> {
+        let repository = Arc::new(InMemoryRepository::default());
+        let new_subscriber = NewSubscriber {
+            email: SubscriberEmail::parse("ursula_le_guin@example.com".into()).unwrap(),
+            name: SubscriberName::parse("le guin".into()).unwrap(),
+        };
+        repository
+            .insert_pending(&new_subscriber, "token")
+            .await
+            .unwrap();
+        repository
+    }
+
+    async fn get_confirmation_with(
+        repository: Arc<dyn TokenRepository>,
+        token: &str,
+        page_settings: ConfirmationPageSettings,
+        accept: Option<&str>,
+    ) -> ServiceResponse {
+        let app = test::init_service(
+            App::new()
+                .route("/subscriptions/confirm", web::get().to(confirm))
+                .app_data(Data::from(repository))
+                .app_data(Data::new(ConfirmationLinkValidity(VALID_FOR)))
+                .app_data(Data::new(page_settings)),
+        )
+        .await;
+        let mut request = test::TestRequest::get().uri(&format!(
+            "/subscriptions/confirm?subscription_token={}",
+            token
+        ));
+        if let Some(accept) = accept {
+            request = request.insert_header(("Accept", accept));
+        }
+        test::call_service(&app, request.to_request()).await
+    }
+
+    async fn get_confirmation(repository: Arc<InMemoryRepository>, token: &str) -> StatusCode {
+        get_confirmation_with(repository, token, ConfirmationPageSettings::default(), None)
+            .await
+            .status()
+    }
+
+    #[actix_web::test]
+    async fn a_valid_token_confirms_the_subscriber() {
+        let repository = repository_with_pending_subscriber().await;
+
+        let status = get_confirmation(repository.clone(), "token").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            repository.subscribers()[0].status,
+            SubscriptionStatus::Confirmed
+        );
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_token_is_rejected_with_a_401() {
+        let repository = repository_with_pending_subscriber().await;
+
+        let status = get_confirmation(repository.clone(), "unknown").await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            repository.subscribers()[0].status,
+            SubscriptionStatus::PendingConfirmation
+        );
+    }
+
+    #[actix_web::test]
+    async fn an_expired_token_is_rejected_and_leaves_the_subscriber_pending() {
+        let repository = repository_with_pending_subscriber().await;
+
+        let error = confirm_subscriber("token", &*repository, Duration::ZERO)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.code(), "expired_token");
+        assert_eq!(error.status_code(), StatusCode::GONE);
+        assert_eq!(error.outcome(), ConfirmationOutcome::ExpiredToken);
+        assert_eq!(
+            repository.subscribers()[0].status,
+            SubscriptionStatus::PendingConfirmation
+        );
+    }
+
+    #[actix_web::test]
+    async fn unsubscribed_subscribers_cannot_be_confirmed_with_an_old_link() {
+        let repository = repository_with_pending_subscriber().await;
+        let subscriber_id = repository.subscribers()[0].id;
+        repository
+            .change_status(subscriber_id, SubscriptionStatus::Unsubscribed)
+            .unwrap();
+
+        let status = get_confirmation(repository.clone(), "token").await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            repository.subscribers()[0].status,
+            SubscriptionStatus::Unsubscribed
+        );
+    }
+
+    #[actix_web::test]
+    async fn browsers_get_the_configured_page() {
+        let repository = repository_with_pending_subscriber().await;
+        let page_settings = ConfirmationPageSettings {
+            brand_name: "Earthsea Gazette".into(),
+            confirmed: PageMessage {
+                title: "Welcome aboard".into(),
+                message: "See you next week.".into(),
+            },
+            ..Default::default()
+        };
+
+        let response = get_confirmation_with(
+            repository,
+            "token",
+            page_settings,
+            Some("text/html,*/*;q=0.8"),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .unwrap(),
+            "text/html; charset=utf-8"
+        );
+        let body = String::from_utf8(
+            test::read_body(response)
+                .await
+                .to_vec(),
+        )
+        .unwrap();
+        assert!(body.contains("Earthsea Gazette"));
+        assert!(body.contains("Welcome aboard"));
+        assert!(body.contains("See you next week."));
+    }
+
+    #[actix_web::test]
+    async fn following_the_link_twice_shows_the_already_confirmed_page() {
+        let repository = repository_with_pending_subscriber().await;
+        get_confirmation(repository.clone(), "token").await;
+
+        let response = get_confirmation_with(
+            repository,
+            "token",
+            ConfirmationPageSettings::default(),
+            Some("application/json"),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], "already_confirmed");
+        assert_eq!(
+            body["message"],
+            ConfirmationPageSettings::default()
+                .already_confirmed
+                .message
+        );
+    }
+
+    #[actix_web::test]
+    async fn api_clients_get_problem_details_even_if_a_redirect_url_is_set() {
+        let repository = repository_with_pending_subscriber().await;
+        let page_settings = ConfirmationPageSettings {
+            redirect_url: Some("https://example.com/welcome".into()),
+            ..Default::default()
+        };
+
+        let response = get_confirmation_with(
+            repository,
+            "unknown",
+            page_settings,
+            Some("application/json"),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .unwrap(),
+            "application/problem+json"
+        );
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "invalid_token");
+    }
+
+    #[actix_web::test]
+    async fn browsers_are_redirected_with_the_outcome_if_a_redirect_url_is_set() {
+        let repository = repository_with_pending_subscriber().await;
+        let page_settings = ConfirmationPageSettings {
+            redirect_url: Some("https://example.com/welcome?lang=en".into()),
+            ..Default::default()
+        };
+
+        let response = get_confirmation_with(repository, "token", page_settings, None).await;
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response
+                .headers()
+                .get(header::LOCATION)
+                .unwrap(),
+            "https://example.com/welcome?lang=en&status=confirmed"
+        );
+    }
+
+    #[actix_web::test]
+    async fn failures_are_kept_for_the_logs_behind_the_error_page() {
+        let response = get_confirmation_with(
+            Arc::new(FailingRepository),
+            "token",
+            ConfirmationPageSettings::default(),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let error = response
+            .response()
+            .error()
+            .expect("The error was not attached to the response.");
+        let details = format!("{:?}", error);
+        assert!(details.contains("Failed to fetch the subscriber of a subscription token."));
+        assert!(details.contains("Caused by:\n\tThe database is unreachable."));
+        let body = String::from_utf8(
+            test::read_body(response)
+                .await
+                .to_vec(),
+        )
+        .unwrap();
+        assert!(body.contains(
+            &ConfirmationPageSettings::default()
+                .error
+                .title
+        ));
+    }
+
+    #[actix_web::test]
+    async fn confirm_errors_map_to_the_status_of_their_page() {
+        use actix_web::ResponseError;
+
+        let refused = ConfirmError::from(ChangeStatusError::InvalidTransition(
+            SubscriptionStatus::Unsubscribed
+                .transition_to(SubscriptionStatus::Confirmed)
+                .unwrap_err(),
+        ));
+        assert!(matches!(refused, ConfirmError::InvalidTransition(_)));
+        assert_eq!(refused.status_code(), StatusCode::CONFLICT);
+        assert_eq!(
+            ConfirmError::UnknownToken.status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        let unknown_subscriber =
+            ConfirmError::from(ChangeStatusError::UnknownSubscriber(Uuid::new_v4()));
+        assert_eq!(
+            unknown_subscriber.status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
 }
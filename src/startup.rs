@@ -1,39 +1,134 @@
-use std::net::TcpListener;
+use std::{
+    net::TcpListener,
+    time::Duration,
+};
 
-use actix_web::{dev::Server, web, web::Data, App, HttpServer};
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use actix_web::{
+    dev::{
+        Server,
+        ServerHandle,
+    },
+    http::Method,
+    middleware::from_fn,
+    web,
+    web::Data,
+    App,
+    HttpServer,
+    Route,
+};
+use anyhow::Context;
+use sqlx::{
+    postgres::PgPoolOptions,
+    PgPool,
+};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    bot_protection::BotProtection,
+    configuration::{
+        DatabaseSettings,
+        Settings,
+    },
+    email_checks::EmailChecks,
     email_client::EmailClient,
-    routes::{confirm, health_check::health_check, publish_newsletter, subscriptions::subscribe},
+    error::problem_details,
+    metrics::{
+        metrics,
+        track_requests,
+    },
+    migrations::run_migrations,
+    rate_limiting::{
+        rate_limit,
+        RateLimiter,
+    },
+    repositories::Repositories,
+    routes::{
+        confirm,
+        docs,
+        health_check::{
+            health_check,
+            liveness,
+            readiness,
+            EmailProviderHealthCache,
+        },
+        openapi_spec,
+        publish_newsletter,
+        subscriptions::subscribe,
+    },
 };
 pub struct Application {
     port: u16,
     server: Server,
+    admin_port: Option<u16>,
+    admin_server: Option<Server>,
+    connection_pool: PgPool,
+}
+
+/// Stops an `Application` gracefully: it stops accepting connections, then
+/// waits for in-flight requests to complete, up to the configured grace
+/// period.
+///
+/// `SIGINT` and `SIGTERM` have the same effect.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    server_handles: Vec<ServerHandle>,
+}
+
+impl ShutdownHandle {
+    pub async fn shutdown(&self) {
+        for server_handle in &self.server_handles {
+            server_handle.stop(true).await;
+        }
+    }
 }
 
 impl Application {
     // We have converted the `build` function into a constructor for
     // `Application`.
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
+        if configuration
+            .database
+            .connect_eagerly
+        {
+            connection_pool
+                .acquire()
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to connect to Postgres at {}:{}.",
+                        configuration.database.host, configuration.database.port
+                    )
+                })?;
+        }
+        if configuration
+            .database
+            .run_migrations_on_startup
+        {
+            run_migrations(&connection_pool)
+                .await
+                .context("Failed to migrate the database on startup.")?;
+        }
+        let shutdown_grace_period = configuration
+            .application
+            .shutdown_grace_period();
         let sender_email = configuration
             .email_client
             .sender()
-            .expect("Invalid sender email address.");
+            .context("Invalid sender email address.")?;
         let timeout = configuration
             .email_client
             .timeout();
         let email_client = EmailClient::new(
             configuration
                 .email_client
-                .base_url,
+                .base_url
+                .clone(),
             sender_email,
             configuration
                 .email_client
-                .authorization_token,
+                .authorization_token
+                .clone(),
             timeout,
         );
         let address = format!(
@@ -47,52 +142,259 @@ impl Application {
             .unwrap()
             .port();
 
+        let (admin_port, admin_server) = match configuration
+            .application
+            .admin_port
+        {
+            Some(admin_port) => {
+                let admin_listener = TcpListener::bind(format!(
+                    "{}:{}",
+                    configuration.application.host, admin_port
+                ))?;
+                let admin_port = admin_listener
+                    .local_addr()
+                    .unwrap()
+                    .port();
+                let admin_server = run_admin(
+                    admin_listener,
+                    connection_pool.clone(),
+                    shutdown_grace_period,
+                )?;
+                (Some(admin_port), Some(admin_server))
+            }
+            None => (None, None),
+        };
+
+        let bot_protection = BotProtection::new(
+            &configuration.bot_protection,
+            configuration
+                .rate_limiting
+                .trust_forwarded_headers,
+        )
+        .context("Failed to build the CAPTCHA client.")?;
+
         let server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
+            Repositories::postgres(connection_pool.clone()),
             email_client,
-            configuration
-                .application
-                .base_url,
+            bot_protection,
+            &configuration,
+            admin_server.is_none(),
         )?;
         // We "save" the bound port in one of `Application`'s fields
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            admin_port,
+            admin_server,
+            connection_pool,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    /// The port operational endpoints are served on, if they are not exposed
+    /// on the main port.
+    pub fn admin_port(&self) -> Option<u16> {
+        self.admin_port
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        let server_handles = std::iter::once(&self.server)
+            .chain(&self.admin_server)
+            .map(Server::handle)
+            .collect();
+        ShutdownHandle { server_handles }
+    }
+
+    /// Serve requests until a shutdown is requested, either via a signal or
+    /// via a `ShutdownHandle`.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let outcome = match self.admin_server {
+            Some(admin_server) => tokio::try_join!(self.server, admin_server).map(|_| ()),
+            None => self.server.await,
+        };
+        // Every request has completed (or has been dropped after the grace
+        // period): nothing is going to use the pool anymore.
+        self.connection_pool
+            .close()
+            .await;
+        tracing::info!("In-flight requests were drained and the database pool was closed. Bye!");
+        outcome
     }
 }
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new().connect_lazy_with(configuration.with_db())
+    PgPoolOptions::new()
+        .max_connections(configuration.max_connections)
+        .min_connections(configuration.min_connections)
+        .acquire_timeout(configuration.acquire_timeout())
+        .idle_timeout(configuration.idle_timeout())
+        .max_lifetime(configuration.max_lifetime())
+        .connect_lazy_with(configuration.with_db())
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// How long the link of a confirmation email can be followed.
+pub struct ConfirmationLinkValidity(pub Duration);
+
+const METRICS_PATH: &str = "/metrics";
+
+/// A route served by `run`.
+pub struct ApiRoute {
+    pub method: Method,
+    pub path: &'static str,
+    /// Whether `ApiDoc` describes the route: `tests/api/openapi.rs` fails if
+    /// the two disagree.
+    pub documented: bool,
+    handler: fn(Route) -> Route,
+}
+
+/// Every route served by `run`, the single source of truth for both the
+/// application and the drift test of the OpenAPI document.
+pub fn api_routes() -> Vec<ApiRoute> {
+    fn route(
+        method: Method,
+        path: &'static str,
+        documented: bool,
+        handler: fn(Route) -> Route,
+    ) -> ApiRoute {
+        ApiRoute {
+            method,
+            path,
+            documented,
+            handler,
+        }
+    }
+
+    vec![
+        route(Method::GET, "/health_check", true, |r| r.to(health_check)),
+        route(Method::GET, "/health/live", true, |r| r.to(liveness)),
+        route(Method::GET, "/health/ready", true, |r| r.to(readiness)),
+        route(Method::POST, "/subscriptions", true, |r| r.to(subscribe)),
+        route(Method::POST, "/newsletters", true, |r| {
+            r.to(publish_newsletter)
+        }),
+        route(Method::GET, "/subscriptions/confirm", true, |r| {
+            r.to(confirm)
+        }),
+        // The documentation does not describe itself.
+        route(Method::GET, "/openapi.json", false, |r| r.to(openapi_spec)),
+        route(Method::GET, "/docs", false, |r| r.to(docs)),
+        // Operational, and served on the admin port when there is one.
+        route(Method::GET, METRICS_PATH, false, |r| r.to(metrics)),
+    ]
+}
+
+/// Serve the API on `listener`, with the operational endpoints too unless
+/// `serve_metrics` is false.
+///
+/// Subscribers and their tokens are stored in `repositories`; `db_pool` is
+/// still used by the other routes.
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
+    repositories: Repositories,
     email_client: EmailClient,
-    base_url: String,
+    bot_protection: BotProtection,
+    configuration: &Settings,
+    serve_metrics: bool,
 ) -> Result<Server, std::io::Error> {
+    let shutdown_grace_period = configuration
+        .application
+        .shutdown_grace_period();
+    let rate_limiter = Data::new(RateLimiter::new(
+        configuration
+            .rate_limiting
+            .clone(),
+        db_pool.clone(),
+    ));
+    let bot_protection = Data::new(bot_protection);
+    let email_checks = Data::new(EmailChecks::new(&configuration.subscriber_email));
     let db_pool = Data::new(db_pool);
+    let subscriber_repository = Data::from(repositories.subscribers);
+    let token_repository = Data::from(repositories.tokens);
     let email_client = Data::new(email_client);
-    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let base_url = Data::new(ApplicationBaseUrl(
+        configuration
+            .application
+            .base_url
+            .clone(),
+    ));
+    let confirmation_link_validity = Data::new(ConfirmationLinkValidity(
+        configuration
+            .application
+            .confirmation_link_validity(),
+    ));
+    let email_provider_health_cache = Data::new(EmailProviderHealthCache::new(
+        configuration
+            .health_check
+            .email_provider_cache_ttl(),
+    ));
+    let confirmation_page_settings = Data::new(
+        configuration
+            .confirmation_page
+            .clone(),
+    );
+    let health_check_settings = Data::new(
+        configuration
+            .health_check
+            .clone(),
+    );
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(track_requests))
+            .wrap(from_fn(problem_details))
             .wrap(TracingLogger::default())
-            .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/newsletters", web::post().to(publish_newsletter))
-            .route("/subscriptions/confirm", web::get().to(confirm))
+            .configure(|cfg| {
+                for route in api_routes() {
+                    if route.path == METRICS_PATH && !serve_metrics {
+                        continue;
+                    }
+                    cfg.route(route.path, (route.handler)(web::method(route.method)));
+                }
+            })
             .app_data(db_pool.clone())
+            .app_data(subscriber_repository.clone())
+            .app_data(token_repository.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(confirmation_link_validity.clone())
+            .app_data(confirmation_page_settings.clone())
+            .app_data(health_check_settings.clone())
+            .app_data(email_provider_health_cache.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_checks.clone())
+    })
+    .shutdown_timeout(shutdown_grace_period.as_secs())
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
+
+/// Serve the operational endpoints on their own listener, to keep them off
+/// the public internet.
+pub fn run_admin(
+    listener: TcpListener,
+    db_pool: PgPool,
+    shutdown_grace_period: Duration,
+) -> Result<Server, std::io::Error> {
+    let db_pool = Data::new(db_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(from_fn(problem_details))
+            .wrap(TracingLogger::default())
+            .route(METRICS_PATH, web::get().to(metrics))
+            .app_data(db_pool.clone())
     })
+    .shutdown_timeout(shutdown_grace_period.as_secs())
     .listen(listener)?
     .run();
 
@@ -1,7 +1,104 @@
-use tracing::{subscriber::set_global_default, Subscriber};
-use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
-use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Mutex,
+};
+
+use once_cell::sync::OnceCell;
+use opentelemetry::{
+    global,
+    propagation::Injector,
+    trace::TracerProvider as _,
+};
+use opentelemetry_otlp::{
+    ExporterBuildError,
+    WithExportConfig,
+};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::SdkTracerProvider,
+    Resource,
+};
+use tracing::{
+    subscriber::{
+        set_global_default,
+        SetGlobalDefaultError,
+    },
+    Subscriber,
+};
+use tracing_appender::{
+    non_blocking::{
+        NonBlocking,
+        WorkerGuard,
+    },
+    rolling::{
+        InitError,
+        RollingFileAppender,
+        Rotation,
+    },
+};
+use tracing_bunyan_formatter::{
+    BunyanFormattingLayer,
+    JsonStorageLayer,
+};
+use tracing_log::{
+    log::SetLoggerError,
+    LogTracer,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::{
+        self,
+        MakeWriter,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    EnvFilter,
+    Layer,
+    Registry,
+};
+
+use crate::{
+    configuration::{
+        LogFileSettings,
+        LogFormat,
+        LogRotation,
+        OtlpSettings,
+        TelemetrySettings,
+    },
+    error::error_chain_fmt,
+};
+
+/// Kept around to flush pending spans on shutdown.
+static TRACER_PROVIDER: OnceCell<SdkTracerProvider> = OnceCell::new();
+/// Kept around to flush pending log lines on shutdown.
+static LOG_FILE_GUARDS: Mutex<Vec<WorkerGuard>> = Mutex::new(Vec::new());
+
+#[derive(thiserror::Error)]
+pub enum TelemetryError {
+    #[error("Failed to build the OTLP span exporter.")]
+    SpanExporter(#[from] ExporterBuildError),
+    #[error("The log file path `{}` must end with a file name.", .0.display())]
+    InvalidLogFilePath(PathBuf),
+    #[error("Failed to open the log file `{}`.", .path.display())]
+    LogFile {
+        path: PathBuf,
+        #[source]
+        source: InitError,
+    },
+    #[error("Failed to redirect `log` records to `tracing`.")]
+    Logger(#[from] SetLoggerError),
+    #[error("Failed to set the global subscriber.")]
+    GlobalSubscriber(#[from] SetGlobalDefaultError),
+}
+
+impl std::fmt::Debug for TelemetryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
 
 /// Compose multiple layers into a `tracing`'s subscriber.
 ///
@@ -13,27 +110,301 @@ use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Regis
 /// We need to explicitly call out that the returned subscriber is
 /// `Send` and `Sync` to make it possible to pass it to `init_subscriber`
 /// later on.
+///
+/// `RUST_LOG`, if set, takes precedence over `settings.level` and
+/// `settings.filters`.
+///
+/// Log files are written from a background thread, so that request handlers
+/// never wait on the disk.
+///
+/// The OpenTelemetry layer is always installed, so that W3C trace context is
+/// propagated even when spans are not exported: spans only leave the process
+/// if `settings.otlp` is set.
 pub fn get_subscriber<Sink>(
     name: String,
-    env_filter: String,
     sink: Sink,
-) -> impl Subscriber + Sync + Send
+    settings: &TelemetrySettings,
+) -> Result<impl Subscriber + Sync + Send, TelemetryError>
 where
     // beautiful higher rank trait bound
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
-    Registry::default()
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(settings.directives()));
+    let tracer_provider = get_tracer_provider(name.clone(), settings.otlp.as_ref())?;
+    let tracer = tracer_provider.tracer(name.clone());
+    global::set_tracer_provider(tracer_provider.clone());
+    // Only the first subscriber gets to flush its spans on shutdown.
+    let _ = TRACER_PROVIDER.set(tracer_provider);
+    let mut formatting_layers = vec![formatting_layer(name.clone(), settings.format, sink, true)];
+    if let Some(file) = &settings.file {
+        let (writer, guard) = log_file(file)?;
+        LOG_FILE_GUARDS
+            .lock()
+            .unwrap()
+            .push(guard);
+        formatting_layers.push(formatting_layer(name, settings.format, writer, false));
+    }
+    Ok(Registry::default()
         .with(env_filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
         .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(formatting_layers))
+}
+
+/// Render events in the requested `format`.
+///
+/// Terminal colours are only used if `ansi` is set, to keep them out of
+/// files.
+fn formatting_layer<S, W>(
+    name: String,
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Bunyan => BunyanFormattingLayer::new(name, writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Compact => fmt::layer()
+            .compact()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+/// Open the log file described by `settings`, behind a writer that hands
+/// lines over to a background thread.
+///
+/// Lines still queued are written when the returned guard is dropped.
+fn log_file(settings: &LogFileSettings) -> Result<(NonBlocking, WorkerGuard), TelemetryError> {
+    let directory = settings
+        .path
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+    let file_name = settings
+        .path
+        .file_name()
+        .ok_or_else(|| TelemetryError::InvalidLogFilePath(settings.path.clone()))?;
+    let rotation = match settings.rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name.to_string_lossy())
+        .build(directory)
+        .map_err(|source| TelemetryError::LogFile {
+            path: settings.path.clone(),
+            source,
+        })?;
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+/// Build a tracer provider that batches finished spans and ships them to an
+/// OTLP/HTTP collector, or discards them if no collector is configured.
+pub fn get_tracer_provider(
+    name: String,
+    otlp: Option<&OtlpSettings>,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let resource = Resource::builder()
+        .with_service_name(name)
+        .build();
+    let mut builder = SdkTracerProvider::builder().with_resource(resource);
+    if let Some(otlp) = otlp {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&otlp.endpoint)
+            .with_timeout(otlp.timeout())
+            .build()?;
+        builder = builder.with_batch_exporter(exporter);
+    }
+    Ok(builder.build())
 }
 
 /// Register a subscriber as global default to process span data.
 /// It should only be called once!
-pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
-    LogTracer::init().expect("Failed to set logger");
-    set_global_default(subscriber).expect("Failed to set subscriber");
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) -> Result<(), TelemetryError> {
+    LogTracer::init()?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// Flush the spans that have not been exported yet and the log lines that
+/// have not been written to the log file yet.
+pub fn shutdown_telemetry() {
+    if let Some(tracer_provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = tracer_provider.shutdown() {
+            tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to shut down the tracer provider."
+            );
+        }
+    }
+    LOG_FILE_GUARDS
+        .lock()
+        .unwrap()
+        .clear();
+}
+
+/// Add the W3C `traceparent` header for the current span to an outgoing
+/// request, so that the receiving end can join our trace.
+pub fn inject_trace_context(headers: &mut reqwest::header::HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+    use wiremock::{
+        matchers::{
+            method,
+            path,
+        },
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{
+        get_tracer_provider,
+        log_file,
+        TelemetryError,
+    };
+    use crate::configuration::{
+        LogFileSettings,
+        LogFormat,
+        LogRotation,
+        OtlpSettings,
+    };
+
+    #[test]
+    fn logs_are_written_to_the_configured_file() {
+        // Arrange
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory
+            .path()
+            .join("logs")
+            .join("zero2prod.log");
+        let settings = LogFileSettings {
+            path: path.clone(),
+            rotation: LogRotation::Never,
+        };
+        let (writer, guard) = log_file(&settings).unwrap();
+        let subscriber = tracing_subscriber::registry().with(super::formatting_layer(
+            "test".into(),
+            LogFormat::Compact,
+            writer,
+            false,
+        ));
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("Written to a file");
+        });
+        // Wait for the background thread to write the line.
+        drop(guard);
+
+        // Assert
+        let logs = std::fs::read_to_string(&path).unwrap();
+        assert!(logs.contains("Written to a file"));
+        // No terminal colours in files
+        assert!(!logs.contains('\u{1b}'));
+    }
+
+    #[test]
+    fn a_log_file_path_without_a_file_name_is_an_error() {
+        let settings = LogFileSettings {
+            path: "/".into(),
+            rotation: LogRotation::Never,
+        };
+
+        assert!(matches!(
+            log_file(&settings),
+            Err(TelemetryError::InvalidLogFilePath(_))
+        ));
+    }
+
+    #[test]
+    fn an_invalid_collector_endpoint_is_an_error() {
+        let settings = OtlpSettings {
+            endpoint: "not a url".into(),
+            timeout_milliseconds: 1000,
+        };
+
+        assert!(get_tracer_provider("test".into(), Some(&settings)).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_configured_collector() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let settings = OtlpSettings {
+            endpoint: format!("{}/v1/traces", collector.uri()),
+            timeout_milliseconds: 1000,
+        };
+        let tracer_provider = get_tracer_provider("test".into(), Some(&settings)).unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(
+                opentelemetry::trace::TracerProvider::tracer(&tracer_provider, "test"),
+            ));
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Exported span").in_scope(|| {});
+        });
+        // Exporting happens on a background thread, off the async runtime.
+        tokio::task::spawn_blocking(move || tracer_provider.force_flush())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Assert
+        let requests = collector
+            .received_requests()
+            .await
+            .unwrap();
+        let exported_span_name = b"Exported span";
+        assert!(requests.iter().any(|r| r
+            .body
+            .windows(exported_span_name.len())
+            .any(|w| w == exported_span_name)));
+    }
 }
@@ -0,0 +1,352 @@
+//! src/testkit.rs
+//!
+//! Spawn the application against a database and an email server of its own,
+//! for our black-box tests and for the services integrating with us.
+//!
+//! Only compiled with the `testkit` feature.
+use once_cell::sync::Lazy;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{
+        method,
+        path,
+    },
+    Mock,
+    MockServer,
+    ResponseTemplate,
+};
+
+use crate::{
+    configuration::{
+        get_configuration,
+        Settings,
+        TelemetrySettings,
+    },
+    domain::SubscriptionStatus,
+    startup::{
+        get_connection_pool,
+        Application,
+        ShutdownHandle,
+    },
+    telemetry::{
+        get_subscriber,
+        init_subscriber,
+    },
+};
+
+mod admin;
+mod database;
+mod inbox;
+
+pub use admin::AdminClient;
+pub use database::{
+    test_database_name,
+    TestDatabase,
+};
+pub use inbox::{
+    CapturedEmail,
+    EmailInbox,
+};
+
+// Ensure that the `tracing` stack is only initialised once using `once_cell`
+static TRACING: Lazy<()> = Lazy::new(|| {
+    let subscriber_name = "test".to_string();
+    if std::env::var("TEST_LOG").is_ok() {
+        let subscriber = get_subscriber(
+            subscriber_name,
+            std::io::stdout,
+            &TelemetrySettings::default(),
+        )
+        .expect("Failed to build the subscriber.");
+        init_subscriber(subscriber).expect("Failed to set the subscriber.");
+    } else {
+        let subscriber = get_subscriber(
+            subscriber_name,
+            std::io::sink,
+            &TelemetrySettings::default(),
+        )
+        .expect("Failed to build the subscriber.");
+        init_subscriber(subscriber).expect("Failed to set the subscriber.");
+    }
+});
+
+pub struct TestApp {
+    pub address: String,
+    /// Set if the application was configured to serve operational endpoints
+    /// on a dedicated port.
+    pub admin_address: Option<String>,
+    pub db_pool: PgPool,
+    pub email_server: MockServer,
+    pub port: u16,
+    /// What the application was built with.
+    pub configuration: Settings,
+    pub shutdown_handle: ShutdownHandle,
+    /// Resolves once the application has stopped.
+    pub server: JoinHandle<Result<(), std::io::Error>>,
+    /// The subscribers stored before the application started, in the order
+    /// they were added to the builder.
+    pub subscribers: Vec<TestSubscriber>,
+    /// Dropped last: the test database goes away with the application.
+    _database: TestDatabase,
+}
+
+/// A subscriber stored by `TestAppBuilder::subscriber`.
+#[derive(Debug, Clone)]
+pub struct TestSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub status: SubscriptionStatus,
+    /// The token of their confirmation link.
+    pub subscription_token: String,
+}
+
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
+}
+
+impl TestApp {
+    pub fn builder<'a>() -> TestAppBuilder<'a> {
+        TestAppBuilder::default()
+    }
+
+    pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The emails the application asked the email provider to send.
+    pub fn inbox(&self) -> EmailInbox<'_> {
+        EmailInbox::new(&self.email_server)
+    }
+
+    /// Create an administrator with random credentials and a client sending
+    /// them along with every request.
+    pub async fn admin_client(&self) -> AdminClient {
+        AdminClient::create(&self.db_pool, &self.address).await
+    }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.confirmation_links(&CapturedEmail::from_request(email_request))
+    }
+
+    /// The confirmation links of `email`, pointing to this application.
+    pub fn confirmation_links(&self, email: &CapturedEmail) -> ConfirmationLinks {
+        // Extract the link from one of the email bodies.
+        let get_link = |links: Vec<reqwest::Url>| {
+            assert_eq!(links.len(), 1);
+            let mut confirmation_link = links
+                .into_iter()
+                .next()
+                .unwrap();
+
+            assert_eq!(
+                confirmation_link
+                    .host_str()
+                    .unwrap(),
+                "127.0.0.1"
+            );
+
+            confirmation_link
+                .set_port(Some(self.port))
+                .unwrap();
+            confirmation_link
+        };
+
+        ConfirmationLinks {
+            html: get_link(email.html_links()),
+            plain_text: get_link(email.text_links()),
+        }
+    }
+
+    /// The link confirming the subscription `subscription_token` was sent
+    /// for.
+    pub fn confirmation_link(&self, subscription_token: &str) -> reqwest::Url {
+        let mut confirmation_link =
+            reqwest::Url::parse(&format!("{}/subscriptions/confirm", self.address)).unwrap();
+        confirmation_link
+            .query_pairs_mut()
+            .append_pair("subscription_token", subscription_token);
+        confirmation_link
+    }
+}
+
+type Customisation<'a> = Box<dyn FnOnce(&mut Settings) + 'a>;
+
+/// Describes the application to spawn.
+///
+/// ```no_run
+/// # use zero2prod::{domain::SubscriptionStatus, testkit::TestApp};
+/// # async fn example() {
+/// let app = TestApp::builder()
+///     .configure(|c| c.subscriber_email.strip_plus_addressing = true)
+///     .subscriber("ursula@example.com", SubscriptionStatus::Confirmed)
+///     .accept_emails()
+///     .spawn()
+///     .await;
+/// # }
+/// ```
+#[derive(Default)]
+pub struct TestAppBuilder<'a> {
+    settings: Option<Settings>,
+    customisations: Vec<Customisation<'a>>,
+    subscribers: Vec<(String, SubscriptionStatus)>,
+    accept_emails: bool,
+}
+
+impl<'a> TestAppBuilder<'a> {
+    /// Start from `settings` rather than from the `configuration` directory
+    /// of the current directory.
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Customise the configuration of the application. The database name,
+    /// the port and the email provider are randomised before `customise` is
+    /// called.
+    pub fn configure(mut self, customise: impl FnOnce(&mut Settings) + 'a) -> Self {
+        self.customisations
+            .push(Box::new(customise));
+        self
+    }
+
+    /// Store a subscriber in `status` before the application starts.
+    pub fn subscriber(mut self, email: impl Into<String>, status: SubscriptionStatus) -> Self {
+        self.subscribers
+            .push((email.into(), status));
+        self
+    }
+
+    /// Have the email provider accept every email, instead of leaving it to
+    /// the test to mount its own mocks.
+    pub fn accept_emails(mut self) -> Self {
+        self.accept_emails = true;
+        self
+    }
+
+    pub async fn spawn(self) -> TestApp {
+        Lazy::force(&TRACING);
+
+        // mock the email server
+        let email_server = MockServer::start().await;
+        if self.accept_emails {
+            Mock::given(path("/email"))
+                .and(method("POST"))
+                .respond_with(ResponseTemplate::new(200))
+                .mount(&email_server)
+                .await;
+        }
+
+        // Randomise configuration to ensure test isolation
+        let configuration = {
+            let mut c = match self.settings {
+                Some(settings) => settings,
+                None => get_configuration().expect("Failed to read configuration."),
+            };
+            // Use a different database for each test case
+            c.database.database_name = test_database_name();
+            // Use a random OS port
+            c.application.port = 0;
+            // randomise email server url
+            c.email_client.base_url = email_server.uri();
+            for customise in self.customisations {
+                customise(&mut c);
+            }
+            c
+        };
+        // Clone the migrated template
+        let database = TestDatabase::migrated(&configuration.database).await;
+        let mut subscribers = Vec::with_capacity(self.subscribers.len());
+        for (email, status) in self.subscribers {
+            subscribers.push(store_subscriber(&database.pool, email, status).await);
+        }
+
+        let application = Application::build(configuration.clone())
+            .await
+            .expect("Failed to build application.");
+
+        let application_port = application.port();
+        let shutdown_handle = application.shutdown_handle();
+        let admin_address = application
+            .admin_port()
+            .map(|port| format!("http://localhost:{}", port));
+
+        let server = tokio::spawn(application.run_until_stopped());
+
+        TestApp {
+            address: format!("http://localhost:{}", application_port),
+            admin_address,
+            port: application_port,
+            db_pool: get_connection_pool(&configuration.database),
+            configuration,
+            email_server,
+            shutdown_handle,
+            server,
+            subscribers,
+            _database: database,
+        }
+    }
+}
+
+pub async fn spawn_app() -> TestApp {
+    TestApp::builder()
+        .spawn()
+        .await
+}
+
+/// Spawn the application after letting the caller customise its
+/// configuration.
+pub async fn spawn_app_with<'a>(customise: impl FnOnce(&mut Settings) + 'a) -> TestApp {
+    TestApp::builder()
+        .configure(customise)
+        .spawn()
+        .await
+}
+
+async fn store_subscriber(
+    pool: &PgPool,
+    email: String,
+    status: SubscriptionStatus,
+) -> TestSubscriber {
+    let id = Uuid::new_v4();
+    let subscription_token = Uuid::new_v4()
+        .simple()
+        .to_string();
+    let name = email
+        .split('@')
+        .next()
+        .unwrap_or_default();
+    sqlx::query(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+         VALUES ($1, $2, $3, now(), $4)",
+    )
+    .bind(id)
+    .bind(&email)
+    .bind(name)
+    .bind(status)
+    .execute(pool)
+    .await
+    .expect("Failed to store a subscriber.");
+    sqlx::query(
+        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)",
+    )
+    .bind(&subscription_token)
+    .bind(id)
+    .execute(pool)
+    .await
+    .expect("Failed to store a subscription token.");
+    TestSubscriber {
+        id,
+        email,
+        status,
+        subscription_token,
+    }
+}
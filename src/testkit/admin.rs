@@ -0,0 +1,72 @@
+//! src/testkit/admin.rs
+use reqwest::{
+    Client,
+    Method,
+    RequestBuilder,
+    Response,
+};
+use secrecy::{
+    ExposeSecret,
+    Secret,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::create_admin;
+
+/// An HTTP client sending the credentials of an administrator with every
+/// request, using HTTP Basic authentication.
+///
+/// No route of this application checks them yet: they are there for
+/// services that authenticate requests against our `users` table.
+pub struct AdminClient {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: Secret<String>,
+    address: String,
+    http_client: Client,
+}
+
+impl AdminClient {
+    pub(super) async fn create(pool: &PgPool, address: &str) -> Self {
+        let username = format!("admin-{}", Uuid::new_v4().simple());
+        let password = Secret::new(
+            Uuid::new_v4()
+                .simple()
+                .to_string(),
+        );
+        let user_id = create_admin(pool, &username, password.clone())
+            .await
+            .expect("Failed to create an administrator.");
+        Self {
+            user_id,
+            username,
+            password,
+            address: address.into(),
+            http_client: Client::new(),
+        }
+    }
+
+    /// A request to `path`, relative to the address of the application.
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http_client
+            .request(method, format!("{}{}", self.address, path))
+            .basic_auth(&self.username, Some(self.password.expose_secret()))
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    pub async fn post_newsletters(&self, body: &serde_json::Value) -> Response {
+        self.post("/newsletters")
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
@@ -0,0 +1,433 @@
+//! src/testkit/database.rs
+//!
+//! Every test gets a database of its own, cloned from a migrated template and
+//! dropped once the test is over.
+//!
+//! Set `TEST_KEEP_DATABASES` to `failed` to keep the databases of the tests
+//! that fail, or to `all` to keep every database.
+use std::{
+    future::Future,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use chrono::Utc;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use sqlx::{
+    Connection,
+    Executor,
+    PgConnection,
+    PgPool,
+};
+use uuid::Uuid;
+
+use crate::{
+    configuration::DatabaseSettings,
+    migrations::MIGRATOR,
+};
+
+/// Every database created by the harness is named with this prefix, and the
+/// sweep leaves any other alone.
+const TEST_DATABASE_PREFIX: &str = "zero2prod_test_";
+const TEMPLATE_PREFIX: &str = "zero2prod_test_template_";
+/// Test databases older than this were left behind by a run that did not
+/// get to drop them, e.g. because it was interrupted.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Templates no run has used for this long were built from migrations of a
+/// branch nobody is testing anymore. Other templates may be in use by a
+/// concurrent run, e.g. from another worktree.
+const TEMPLATE_STALE_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// The comment of templates, followed by the last time a run used them.
+const TEMPLATE_USED_COMMENT: &str = "last used by the test suite at ";
+/// The comment of databases kept for debugging, which are never swept.
+const KEPT_COMMENT: &str = "kept by the test suite";
+/// The key of the advisory lock serialising test runs while they sweep and
+/// build the template.
+const TEMPLATE_LOCK_ID: i64 = 0x7465_7374_5f74_706c; // "test_tpl"
+
+/// The name of a new test database: it records when it was created, so that
+/// stale ones can be swept.
+pub fn test_database_name() -> String {
+    format!(
+        "{}{}_{}",
+        TEST_DATABASE_PREFIX,
+        Utc::now().timestamp(),
+        Uuid::new_v4().simple()
+    )
+}
+
+/// A database created for a single test, dropped with it.
+pub struct TestDatabase {
+    pub settings: DatabaseSettings,
+    pub pool: PgPool,
+}
+
+impl TestDatabase {
+    /// A fully migrated database, cloned from the template.
+    pub async fn migrated(settings: &DatabaseSettings) -> Self {
+        let template = template_database(settings);
+        Self::create(settings, Some(template)).await
+    }
+
+    /// A database without any migration applied.
+    pub async fn empty(settings: &DatabaseSettings) -> Self {
+        sweep_once(settings);
+        Self::create(settings, None).await
+    }
+
+    async fn create(settings: &DatabaseSettings, template: Option<&str>) -> Self {
+        let mut connection = PgConnection::connect_with(&settings.without_db())
+            .await
+            .expect("Failed to connect to Postgres");
+        let template = template
+            .map(|template| format!(r#" TEMPLATE "{}""#, template))
+            .unwrap_or_default();
+        connection
+            .execute(
+                format!(
+                    r#"CREATE DATABASE "{}"{};"#,
+                    settings.database_name, template
+                )
+                .as_str(),
+            )
+            .await
+            .expect("Failed to create database.");
+        let pool = PgPool::connect_with(settings.with_db())
+            .await
+            .expect("Failed to connect to Postgres.");
+        Self {
+            settings: settings.clone(),
+            pool,
+        }
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let failed = std::thread::panicking();
+        let keep = match std::env::var("TEST_KEEP_DATABASES").as_deref() {
+            Ok("all") => true,
+            Ok("failed") => failed,
+            _ => false,
+        };
+        let settings = &self.settings;
+        let outcome = block_on_new_runtime(async {
+            let mut connection = PgConnection::connect_with(&settings.without_db()).await?;
+            let statement = if keep {
+                format!(
+                    r#"COMMENT ON DATABASE "{}" IS '{}';"#,
+                    settings.database_name, KEPT_COMMENT
+                )
+            } else {
+                // Connections still open, e.g. in the pool of the application,
+                // are terminated.
+                format!(
+                    r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE);"#,
+                    settings.database_name
+                )
+            };
+            connection
+                .execute(statement.as_str())
+                .await
+                .map(|_| ())
+        });
+        match outcome {
+            Ok(()) if keep => eprintln!(
+                "Kept the test database `{}`: drop it with `DROP DATABASE \"{}\";` once done.",
+                settings.database_name, settings.database_name
+            ),
+            Ok(()) => {}
+            // Panicking while unwinding would abort the whole test binary.
+            Err(e) => eprintln!(
+                "Failed to clean up the test database `{}`: {}",
+                settings.database_name, e
+            ),
+        }
+    }
+}
+
+/// Sweep the leftovers of previous runs, once per test binary.
+fn sweep_once(settings: &DatabaseSettings) {
+    static SWEPT: OnceLock<()> = OnceLock::new();
+    SWEPT.get_or_init(|| {
+        block_on_new_runtime(async {
+            let mut connection = PgConnection::connect_with(&settings.without_db()).await?;
+            lock(&mut connection).await?;
+            sweep(&mut connection).await?;
+            unlock(&mut connection).await
+        })
+        .expect("Failed to sweep stale test databases.")
+    });
+}
+
+/// The name of the template the test databases are cloned from, built once
+/// per test binary if the migrations changed since the last run.
+fn template_database(settings: &DatabaseSettings) -> &'static str {
+    static TEMPLATE: OnceLock<String> = OnceLock::new();
+    sweep_once(settings);
+    TEMPLATE.get_or_init(|| {
+        block_on_new_runtime(build_template(settings.clone()))
+            .expect("Failed to build the template database.")
+    })
+}
+
+async fn build_template(mut settings: DatabaseSettings) -> Result<String, sqlx::Error> {
+    settings.database_name = template_name();
+    let mut connection = PgConnection::connect_with(&settings.without_db()).await?;
+    // Concurrent test runs must not build the same template twice.
+    lock(&mut connection).await?;
+
+    // A template is only flagged as such once fully migrated: anything else
+    // was left half-built by an interrupted run.
+    let is_template: Option<bool> =
+        sqlx::query_scalar("SELECT datistemplate FROM pg_database WHERE datname = $1")
+            .bind(&settings.database_name)
+            .fetch_optional(&mut connection)
+            .await?;
+    if is_template != Some(true) {
+        connection
+            .execute(format!(r#"DROP DATABASE IF EXISTS "{}";"#, settings.database_name).as_str())
+            .await?;
+        connection
+            .execute(format!(r#"CREATE DATABASE "{}";"#, settings.database_name).as_str())
+            .await?;
+        mark_template_as_used(&mut connection, &settings.database_name).await?;
+        let pool = PgPool::connect_with(settings.with_db()).await?;
+        MIGRATOR
+            .run(&pool)
+            .await
+            .map_err(|e| sqlx::Error::Migrate(Box::new(e)))?;
+        // Databases cannot be cloned while someone is connected to them.
+        pool.close().await;
+        connection
+            .execute(
+                format!(
+                    r#"ALTER DATABASE "{}" WITH IS_TEMPLATE true;"#,
+                    settings.database_name
+                )
+                .as_str(),
+            )
+            .await?;
+    } else {
+        mark_template_as_used(&mut connection, &settings.database_name).await?;
+    }
+
+    unlock(&mut connection).await?;
+    Ok(settings.database_name)
+}
+
+/// Record that a run is using `template`, to keep other runs from sweeping
+/// it.
+async fn mark_template_as_used(
+    connection: &mut PgConnection,
+    template: &str,
+) -> Result<(), sqlx::Error> {
+    connection
+        .execute(
+            format!(
+                r#"COMMENT ON DATABASE "{}" IS '{}{}';"#,
+                template,
+                TEMPLATE_USED_COMMENT,
+                Utc::now().timestamp()
+            )
+            .as_str(),
+        )
+        .await
+        .map(|_| ())
+}
+
+/// Named after the migrations, so that changing them leads to a new
+/// template. The hash must not depend on the toolchain: runs built by
+/// different compilers share the template.
+fn template_name() -> String {
+    let mut hasher = Sha256::new();
+    for migration in MIGRATOR.iter() {
+        hasher.update(
+            migration
+                .version
+                .to_le_bytes(),
+        );
+        hasher.update(&migration.checksum);
+    }
+    let hash: String = hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}{}", TEMPLATE_PREFIX, hash)
+}
+
+/// Drop the test databases left behind by previous runs, as well as
+/// templates no run has used for a while. Only databases named by the
+/// harness are considered.
+async fn sweep(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let databases: Vec<(String, bool, Option<String>)> = sqlx::query_as(
+        "SELECT datname, datistemplate, shobj_description(oid, 'pg_database') \
+         FROM pg_database \
+         WHERE shobj_description(oid, 'pg_database') IS DISTINCT FROM $1",
+    )
+    .bind(KEPT_COMMENT)
+    .fetch_all(&mut *connection)
+    .await?;
+    let current_template = template_name();
+    for (database, is_template, comment) in databases {
+        if !is_stale(
+            &database,
+            comment.as_deref(),
+            &current_template,
+            Utc::now().timestamp(),
+        ) {
+            continue;
+        }
+        if is_template {
+            connection
+                .execute(
+                    format!(r#"ALTER DATABASE "{}" WITH IS_TEMPLATE false;"#, database).as_str(),
+                )
+                .await?;
+        }
+        connection
+            .execute(format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE);"#, database).as_str())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Whether `database`, commented with `comment`, was left behind by the
+/// harness at `now`: an old test database or a template no run has used for
+/// a while.
+fn is_stale(database: &str, comment: Option<&str>, current_template: &str, now: i64) -> bool {
+    if database.starts_with(TEMPLATE_PREFIX) {
+        // Templates built before their use was recorded are stale too.
+        let last_used = comment
+            .and_then(|c| c.strip_prefix(TEMPLATE_USED_COMMENT))
+            .and_then(|timestamp| timestamp.parse::<i64>().ok())
+            .unwrap_or(i64::MIN);
+        database != current_template
+            && now.saturating_sub(last_used) > TEMPLATE_STALE_AFTER.as_secs() as i64
+    } else if let Some(created_at) = created_at(database) {
+        now - created_at > STALE_AFTER.as_secs() as i64
+    } else {
+        false
+    }
+}
+
+/// When a database named by `test_database_name` was created.
+fn created_at(database: &str) -> Option<i64> {
+    let (created_at, id) = database
+        .strip_prefix(TEST_DATABASE_PREFIX)?
+        .split_once('_')?;
+    Uuid::try_parse(id).ok()?;
+    created_at.parse().ok()
+}
+
+async fn lock(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(TEMPLATE_LOCK_ID)
+        .execute(connection)
+        .await
+        .map(|_| ())
+}
+
+async fn unlock(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(TEMPLATE_LOCK_ID)
+        .execute(connection)
+        .await
+        .map(|_| ())
+}
+
+/// Run `future` to completion from synchronous code, even from within the
+/// runtime of a test.
+fn block_on_new_runtime<F>(future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to build a Tokio runtime.")
+                    .block_on(future)
+            })
+            .join()
+            .expect("The thread driving the future panicked.")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{
+        is_stale,
+        template_name,
+        test_database_name,
+        STALE_AFTER,
+        TEMPLATE_PREFIX,
+        TEMPLATE_STALE_AFTER,
+        TEMPLATE_USED_COMMENT,
+    };
+
+    const HOUR: i64 = 60 * 60;
+
+    #[test]
+    fn only_old_test_databases_are_stale() {
+        let database = test_database_name();
+        let now = chrono::Utc::now().timestamp();
+
+        assert!(!is_stale(&database, None, &template_name(), now));
+        assert!(is_stale(
+            &database,
+            None,
+            &template_name(),
+            now + STALE_AFTER.as_secs() as i64 + 1
+        ));
+    }
+
+    #[test]
+    fn only_templates_unused_for_a_while_are_stale() {
+        let other_template = format!("{}{:016x}", TEMPLATE_PREFIX, 0);
+        let now = chrono::Utc::now().timestamp();
+        let used_now = format!("{}{}", TEMPLATE_USED_COMMENT, now);
+        let later = now + TEMPLATE_STALE_AFTER.as_secs() as i64 + 1;
+
+        // A concurrent run, e.g. from another branch, may be using it.
+        assert!(!is_stale(
+            &other_template,
+            Some(&used_now),
+            &template_name(),
+            now
+        ));
+        assert!(is_stale(
+            &other_template,
+            Some(&used_now),
+            &template_name(),
+            later
+        ));
+        assert!(is_stale(&other_template, None, &template_name(), now));
+        assert!(!is_stale(&template_name(), None, &template_name(), later));
+    }
+
+    #[test]
+    fn databases_not_named_by_the_harness_are_never_stale() {
+        let now = chrono::Utc::now().timestamp() + 24 * HOUR;
+        for database in [
+            Uuid::new_v4().to_string(),
+            format!("test_{}_{}", 0, Uuid::new_v4().simple()),
+            "test_template_0123456789abcdef".to_string(),
+            "newsletter".to_string(),
+        ] {
+            assert!(
+                !is_stale(&database, None, &template_name(), now),
+                "{}",
+                database
+            );
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! src/testkit/inbox.rs
+use wiremock::{
+    MockServer,
+    Request,
+};
+
+/// The emails received by the mock email provider, oldest first.
+pub struct EmailInbox<'a> {
+    email_server: &'a MockServer,
+}
+
+/// An email, as the application asked the email provider to send it.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CapturedEmail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+impl<'a> EmailInbox<'a> {
+    pub(super) fn new(email_server: &'a MockServer) -> Self {
+        Self { email_server }
+    }
+
+    pub async fn emails(&self) -> Vec<CapturedEmail> {
+        self.email_server
+            .received_requests()
+            .await
+            .expect("Request recording is disabled on the email server.")
+            .iter()
+            .filter(|request| request.url.path() == "/email")
+            .map(CapturedEmail::from_request)
+            .collect()
+    }
+
+    /// The emails sent to `recipient`.
+    pub async fn emails_to(&self, recipient: &str) -> Vec<CapturedEmail> {
+        self.emails()
+            .await
+            .into_iter()
+            .filter(|email| email.to == recipient)
+            .collect()
+    }
+
+    /// The most recent email sent to `recipient`.
+    ///
+    /// Panics if there is none.
+    pub async fn last_email_to(&self, recipient: &str) -> CapturedEmail {
+        self.emails_to(recipient)
+            .await
+            .pop()
+            .unwrap_or_else(|| panic!("No email was sent to {}.", recipient))
+    }
+}
+
+impl CapturedEmail {
+    /// Panics if `request` is not a request to send an email.
+    pub fn from_request(request: &Request) -> Self {
+        serde_json::from_slice(&request.body).expect("Failed to parse the email request.")
+    }
+
+    /// The links in the HTML body.
+    pub fn html_links(&self) -> Vec<reqwest::Url> {
+        links(&self.html_body)
+    }
+
+    /// The links in the plain text body.
+    pub fn text_links(&self) -> Vec<reqwest::Url> {
+        links(&self.text_body)
+    }
+}
+
+fn links(s: &str) -> Vec<reqwest::Url> {
+    linkify::LinkFinder::new()
+        .links(s)
+        .filter(|l| *l.kind() == linkify::LinkKind::Url)
+        .map(|l| reqwest::Url::parse(l.as_str()).unwrap())
+        .collect()
+}
@@ -0,0 +1,208 @@
+use secrecy::Secret;
+use wiremock::{
+    matchers::{
+        any,
+        body_string_contains,
+        path,
+    },
+    Mock,
+    MockServer,
+    ResponseTemplate,
+};
+use zero2prod::configuration::CaptchaSettings;
+
+use crate::helpers::{
+    spawn_app,
+    spawn_app_with,
+    TestApp,
+};
+
+async fn accept_emails(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// Nothing was stored and no email was sent.
+async fn assert_turned_away(app: &TestApp) {
+    let subscribers: i64 = sqlx::query_scalar("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 0);
+    let emails = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap();
+    assert!(emails.is_empty());
+}
+
+async fn assert_rejected_with(response: reqwest::Response, code: &str) {
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["detail"].is_string());
+    assert_eq!(body["code"], code);
+}
+
+fn captcha(verify_server: &MockServer) -> CaptchaSettings {
+    CaptchaSettings {
+        verify_url: format!("{}/siteverify", verify_server.uri()),
+        secret_key: Secret::new("secret-key".into()),
+        timeout_milliseconds: 1000,
+    }
+}
+
+#[tokio::test]
+async fn subscribe_ignores_forms_with_a_filled_in_honeypot_without_telling() {
+    // Arrange
+    let app = spawn_app().await;
+    accept_emails(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example"
+                .into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_turned_away(&app).await;
+    let metrics = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics
+        .lines()
+        .any(|line| line
+            .strip_prefix("honeypot_submissions_total ")
+            .and_then(|value| value.parse::<f64>().ok())
+            .is_some_and(|value| value >= 1.0)));
+}
+
+#[tokio::test]
+async fn subscribe_rejects_forms_submitted_too_quickly() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection
+            .minimum_seconds_to_submit = Some(60)
+    })
+    .await;
+    accept_emails(&app).await;
+    let now = chrono::Utc::now().timestamp();
+
+    // Act
+    let too_quick = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&rendered_at={}",
+            now - 5
+        ))
+        .await;
+    let undated = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_rejected_with(too_quick, "submitted_too_quickly").await;
+    assert_rejected_with(undated, "submitted_too_quickly").await;
+    assert_turned_away(&app).await;
+
+    // A person takes their time.
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&rendered_at={}",
+            now - 120
+        ))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_solved_captchas() {
+    // Arrange
+    let verify_server = MockServer::start().await;
+    let app = spawn_app_with(|c| c.bot_protection.captcha = Some(captcha(&verify_server))).await;
+    accept_emails(&app).await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=solution"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .expect(1)
+        .mount(&verify_server)
+        .await;
+
+    // Act
+    // The field name used by the hCaptcha widget.
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&h-captcha-response=solution".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_missing_or_wrong_captcha_solutions() {
+    // Arrange
+    let verify_server = MockServer::start().await;
+    let app = spawn_app_with(|c| c.bot_protection.captcha = Some(captcha(&verify_server))).await;
+    accept_emails(&app).await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false,
+            "error-codes": ["invalid-input-response"]
+        })))
+        .expect(1)
+        .mount(&verify_server)
+        .await;
+
+    // Act
+    let missing = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let wrong = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&captcha_response=guess".into(),
+        )
+        .await;
+
+    // Assert
+    assert_rejected_with(missing, "captcha_missing").await;
+    assert_rejected_with(wrong, "captcha_rejected").await;
+    assert_turned_away(&app).await;
+}
+
+#[tokio::test]
+async fn subscribe_returns_500_if_the_captcha_provider_is_down() {
+    // Arrange
+    let verify_server = MockServer::start().await;
+    let app = spawn_app_with(|c| c.bot_protection.captcha = Some(captcha(&verify_server))).await;
+    accept_emails(&app).await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&verify_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&captcha_response=solution".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    assert_turned_away(&app).await;
+}
@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+
+use claims::{
+    assert_err,
+    assert_ok,
+};
+use uuid::Uuid;
+use zero2prod::{
+    cli::{
+        run,
+        Command,
+        ConfigCommand,
+        SubscribersCommand,
+    },
+    domain::SubscriptionStatus,
+};
+
+use crate::helpers::{
+    spawn_app,
+    TestApp,
+};
+
+fn temp_file(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}.csv", Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+async fn subscribers(app: &TestApp, command: SubscribersCommand) -> Result<(), anyhow::Error> {
+    run(Command::Subscribers { command }, app.configuration.clone()).await
+}
+
+async fn count_subscribers(app: &TestApp) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn imported_subscribers_can_be_exported() {
+    // Arrange
+    let app = spawn_app().await;
+    let input = temp_file("email,name\nursula_le_guin@gmail.com,le guin\n");
+    let output = std::env::temp_dir().join(format!("{}.csv", Uuid::new_v4()));
+
+    // Act
+    assert_ok!(
+        subscribers(
+            &app,
+            SubscribersCommand::Import {
+                path: input,
+                status: SubscriptionStatus::Confirmed,
+            },
+        )
+        .await
+    );
+    assert_ok!(
+        subscribers(
+            &app,
+            SubscribersCommand::Export {
+                output: Some(output.clone()),
+                status: None,
+            },
+        )
+        .await
+    );
+
+    // Assert
+    let exported = std::fs::read_to_string(output).unwrap();
+    let mut lines = exported.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("ursula_le_guin@gmail.com,le guin,confirmed,"));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn importing_known_subscribers_again_is_a_no_op() {
+    // Arrange
+    let app = spawn_app().await;
+    let input = temp_file("email,name\nursula_le_guin@gmail.com,le guin\n");
+    let import = || SubscribersCommand::Import {
+        path: input.clone(),
+        status: SubscriptionStatus::Confirmed,
+    };
+
+    // Act
+    assert_ok!(subscribers(&app, import()).await);
+    assert_ok!(subscribers(&app, import()).await);
+
+    // Assert
+    assert_eq!(count_subscribers(&app).await, 1);
+}
+
+#[tokio::test]
+async fn an_invalid_row_aborts_the_whole_import() {
+    // Arrange
+    let app = spawn_app().await;
+    let input =
+        temp_file("email,name\nursula_le_guin@gmail.com,le guin\ndefinitely-not-an-email,bob\n");
+
+    // Act
+    let outcome = subscribers(
+        &app,
+        SubscribersCommand::Import {
+            path: input,
+            status: SubscriptionStatus::Confirmed,
+        },
+    )
+    .await;
+
+    // Assert
+    let error = assert_err!(outcome);
+    assert!(error
+        .to_string()
+        .contains("line 3"));
+    assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn delete_removes_a_pending_subscriber_and_their_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    wiremock::Mock::given(wiremock::matchers::any())
+        .respond_with(wiremock::ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let outcome = subscribers(
+        &app,
+        SubscribersCommand::Delete {
+            email: "ursula_le_guin@gmail.com".into(),
+        },
+    )
+    .await;
+
+    // Assert
+    assert_ok!(outcome);
+    assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn deleting_an_unknown_subscriber_fails() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = subscribers(
+        &app,
+        SubscribersCommand::Delete {
+            email: "ursula_le_guin@gmail.com".into(),
+        },
+    )
+    .await;
+
+    // Assert
+    assert_err!(outcome);
+}
+
+#[tokio::test]
+async fn create_admin_stores_a_hashed_password() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = run(
+        Command::CreateAdmin {
+            username: "admin".into(),
+            password: Some("everythinghastostartsomewhere".into()),
+        },
+        app.configuration.clone(),
+    )
+    .await;
+
+    // Assert
+    assert_ok!(outcome);
+    let password_hash: String =
+        sqlx::query_scalar("SELECT password_hash FROM users WHERE username = 'admin'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(password_hash.starts_with("$argon2id$"));
+}
+
+#[tokio::test]
+async fn config_check_reports_every_invalid_setting() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration
+        .email_client
+        .sender_email = "not-an-email".into();
+    configuration
+        .application
+        .base_url = "127.0.0.1".into();
+
+    // Act
+    let outcome = run(
+        Command::Config {
+            command: ConfigCommand::Check,
+        },
+        configuration,
+    )
+    .await;
+
+    // Assert
+    let error = format!("{:?}", assert_err!(outcome));
+    assert!(error.contains("email_client.sender_email"));
+    assert!(error.contains("application.base_url"));
+}
@@ -0,0 +1,79 @@
+use uuid::Uuid;
+use zero2prod::{
+    configuration::get_configuration,
+    startup::Application,
+};
+
+use crate::helpers::spawn_app_with;
+
+#[tokio::test]
+async fn connections_carry_the_configured_session_settings() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.database.application_name = "zero2prod-test".into();
+        c.database
+            .statement_timeout_milliseconds = Some(1234);
+    })
+    .await;
+
+    // Act
+    let application_name: String = sqlx::query_scalar("SHOW application_name")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let statement_timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(application_name, "zero2prod-test");
+    assert_eq!(statement_timeout, "1234ms");
+}
+
+#[tokio::test]
+async fn statements_exceeding_the_timeout_are_aborted() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.database
+            .statement_timeout_milliseconds = Some(100);
+    })
+    .await;
+
+    // Act
+    let outcome = sqlx::query("SELECT pg_sleep(1)")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn startup_fails_fast_if_the_database_is_unreachable_and_eager_connect_is_on() {
+    // Arrange
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration
+        .database
+        .database_name = Uuid::new_v4().to_string();
+    configuration
+        .database
+        .connect_eagerly = true;
+    configuration
+        .database
+        .acquire_timeout_milliseconds = 500;
+    // Nothing is listening there.
+    configuration.database.port = 1;
+    configuration.application.port = 0;
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    let error = outcome
+        .err()
+        .expect("Startup should have failed.");
+    assert!(error
+        .to_string()
+        .contains("Failed to connect to Postgres"));
+}
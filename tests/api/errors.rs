@@ -0,0 +1,105 @@
+//! tests/api/errors.rs
+
+use crate::helpers::spawn_app;
+
+async fn problem_details(response: reqwest::Response) -> serde_json::Value {
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn unexpected_errors_do_not_leak_their_details() {
+    // Arrange
+    let app = spawn_app().await;
+    // Sabotage the database
+    sqlx::query("ALTER TABLE subscriptions DROP COLUMN email;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let body = problem_details(response).await;
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Internal Server Error");
+    assert_eq!(body["status"], 500);
+    assert_eq!(body["code"], "internal_server_error");
+    assert!(body.get("detail").is_none());
+    assert!(!body
+        .to_string()
+        .contains("column"));
+}
+
+#[tokio::test]
+async fn every_problem_reports_the_id_of_its_request() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = app
+        .post_subscriptions("name=&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let second = app
+        .post_subscriptions("name=&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let first = problem_details(first).await;
+    let second = problem_details(second).await;
+    let first_id: uuid::Uuid = first["request_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let second_id: uuid::Uuid = second["request_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_ne!(first_id, second_id);
+}
+
+#[tokio::test]
+async fn malformed_requests_are_problems_too() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body = problem_details(response).await;
+    assert_eq!(body["code"], "bad_request");
+    assert!(body["detail"]
+        .as_str()
+        .unwrap()
+        .contains("email"));
+}
+
+#[tokio::test]
+async fn unknown_routes_are_problems_too() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/unsubscribe", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let body = problem_details(response).await;
+    assert_eq!(body["code"], "not_found");
+    assert!(body["request_id"].is_string());
+}
@@ -1,4 +1,13 @@
-use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::method,
+    Mock,
+    ResponseTemplate,
+};
+
+use crate::helpers::{
+    spawn_app,
+    spawn_app_with,
+};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -18,3 +27,97 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_returns_a_200() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/health/live", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_reports_every_component_as_up() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.health_check
+            .check_email_provider = true
+    })
+    .await;
+    // Any answer proves that the provider is reachable.
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    for component in ["database", "migrations", "email_provider"] {
+        assert_eq!(body["components"][component]["status"], "up");
+        assert!(body["components"][component]["latency_ms"].is_u64());
+    }
+}
+
+#[tokio::test]
+async fn readiness_returns_a_503_if_migrations_are_pending() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["migrations"]["status"], "down");
+}
+
+#[tokio::test]
+async fn readiness_caches_the_email_provider_probe() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.health_check
+            .check_email_provider = true
+    })
+    .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..3 {
+        reqwest::get(format!("{}/health/ready", &app.address))
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    // Assert
+    // Mock asserts on drop
+}
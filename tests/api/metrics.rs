@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use sqlx::postgres::PgPoolOptions;
+use wiremock::{
+    matchers::{
+        method,
+        path,
+    },
+    Mock,
+    ResponseTemplate,
+};
+use zero2prod::metrics::begin_transaction;
+
+use crate::helpers::{
+    spawn_app,
+    spawn_app_with,
+    TestApp,
+};
+
+async fn scrape(app: &TestApp) -> String {
+    reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+/// The value of the sample `name`, labels included, or 0 if it was not
+/// recorded yet.
+///
+/// Metrics are shared by the applications of every test of this binary: only
+/// assert on lower bounds of how much they changed.
+fn sample(scrape: &str, name: &str) -> f64 {
+    scrape
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(' ')?
+                .parse()
+                .ok()
+        })
+        .unwrap_or(0.0)
+}
+
+#[tokio::test]
+async fn metrics_expose_http_requests_per_route_and_status() {
+    // Arrange
+    let app = spawn_app().await;
+    let requests = r#"http_requests_total{method="GET",route="/health_check",status="200"}"#;
+    let durations =
+        r#"http_request_duration_seconds_count{method="GET",route="/health_check",status="200"}"#;
+    let before = scrape(&app).await;
+    for _ in 0..3 {
+        reqwest::get(format!("{}/health_check", app.address))
+            .await
+            .unwrap();
+    }
+
+    // Act
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let after = response.text().await.unwrap();
+    assert!(sample(&after, requests) - sample(&before, requests) >= 3.0);
+    assert!(sample(&after, durations) - sample(&before, durations) >= 3.0);
+}
+
+#[tokio::test]
+async fn metrics_expose_email_sends() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let sent = r#"emails_sent_total{outcome="success"}"#;
+    let send_durations = "email_send_duration_seconds_count";
+    let acquisitions = "db_pool_acquire_duration_seconds_count";
+    let before = scrape(&app).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let after = scrape(&app).await;
+
+    // Assert
+    assert!(sample(&after, sent) - sample(&before, sent) >= 1.0);
+    assert!(sample(&after, send_durations) - sample(&before, send_durations) >= 1.0);
+    // Storing the subscriber took a connection from the pool.
+    assert!(sample(&after, acquisitions) - sample(&before, acquisitions) >= 1.0);
+    assert!(sample(&after, "db_pool_connections") >= 1.0);
+}
+
+#[tokio::test]
+async fn waiting_for_a_connection_of_a_full_pool_is_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(
+            app.configuration
+                .database
+                .with_db(),
+        )
+        .await
+        .unwrap();
+    let held = pool.acquire().await.unwrap();
+    let waits = "db_pool_acquire_waits_total";
+    let before = scrape(&app).await;
+
+    // Act
+    let (transaction, ()) = tokio::join!(begin_transaction(&pool), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(held);
+    });
+
+    // Assert
+    transaction.unwrap();
+    let after = scrape(&app).await;
+    assert!(sample(&after, waits) - sample(&before, waits) >= 1.0);
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_dedicated_admin_port() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.admin_port = Some(0)).await;
+    let admin_address = app
+        .admin_address
+        .clone()
+        .expect("The admin server was not started.");
+
+    // Act
+    let on_admin_port = reqwest::get(format!("{}/metrics", admin_address))
+        .await
+        .expect("Failed to execute request.");
+    let on_public_port = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(
+        on_admin_port
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(
+        on_public_port
+            .status()
+            .as_u16(),
+        404
+    );
+}
@@ -0,0 +1,221 @@
+use chrono::{
+    Duration,
+    Utc,
+};
+use uuid::Uuid;
+use zero2prod::{
+    configuration::{
+        get_configuration,
+        Settings,
+    },
+    migrations::MIGRATOR,
+    startup::Application,
+    testkit::{
+        test_database_name,
+        TestDatabase,
+    },
+};
+
+/// A configuration pointing to a brand-new database, with migrations on
+/// startup enabled.
+fn configuration() -> Settings {
+    let mut c = get_configuration().expect("Failed to read configuration.");
+    c.database.database_name = test_database_name();
+    c.database
+        .run_migrations_on_startup = true;
+    c.application.port = 0;
+    c
+}
+
+async fn table_exists(pool: &sqlx::PgPool, table: &str) -> bool {
+    sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(table)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+/// Run the SQL of the migrations in `versions`, without any bookkeeping.
+async fn apply_migrations(pool: &sqlx::PgPool, versions: impl Fn(i64) -> bool) {
+    for migration in MIGRATOR
+        .iter()
+        .filter(|m| versions(m.version))
+    {
+        sqlx::raw_sql(&migration.sql)
+            .execute(pool)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to apply {}: {}", migration.version, e));
+    }
+}
+
+#[tokio::test]
+async fn migrations_are_applied_on_startup_when_enabled() {
+    // Arrange
+    let configuration = configuration();
+    let database = TestDatabase::empty(&configuration.database).await;
+    let pool = &database.pool;
+
+    // Act
+    Application::build(configuration)
+        .await
+        .expect("Failed to build application.");
+
+    // Assert
+    assert!(table_exists(pool, "subscriptions").await);
+    assert!(table_exists(pool, "subscription_tokens").await);
+}
+
+#[tokio::test]
+async fn migrations_are_not_applied_on_startup_when_disabled() {
+    // Arrange
+    let mut configuration = configuration();
+    configuration
+        .database
+        .run_migrations_on_startup = false;
+    let database = TestDatabase::empty(&configuration.database).await;
+    let pool = &database.pool;
+
+    // Act
+    Application::build(configuration)
+        .await
+        .expect("Failed to build application.");
+
+    // Assert
+    assert!(!table_exists(pool, "subscriptions").await);
+}
+
+#[tokio::test]
+async fn concurrent_instances_can_migrate_the_same_database() {
+    // Arrange
+    let configuration = configuration();
+    let database = TestDatabase::empty(&configuration.database).await;
+    let pool = &database.pool;
+
+    // Act
+    let (a, b, c) = tokio::join!(
+        Application::build(configuration.clone()),
+        Application::build(configuration.clone()),
+        Application::build(configuration.clone()),
+    );
+
+    // Assert
+    a.expect("Failed to build application.");
+    b.expect("Failed to build application.");
+    c.expect("Failed to build application.");
+    assert!(table_exists(pool, "subscriptions").await);
+}
+
+#[tokio::test]
+async fn startup_is_refused_if_the_schema_is_newer_than_the_binary() {
+    // Arrange
+    let configuration = configuration();
+    let database = TestDatabase::empty(&configuration.database).await;
+    let pool = &database.pool;
+    Application::build(configuration.clone())
+        .await
+        .expect("Failed to build application.");
+    // Pretend a more recent release applied one more migration
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+         VALUES (99990101000000, 'from the future', true, '\\x00', 0)",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    let error = match outcome {
+        Ok(_) => panic!("The application started on a schema newer than the binary."),
+        Err(e) => e,
+    };
+    assert!(format!("{:?}", error).contains("99990101000000"));
+}
+
+#[tokio::test]
+async fn subscribers_differing_only_by_email_case_are_merged() {
+    // Arrange
+    const CASE_INSENSITIVE_EMAILS: i64 = 20261018150000;
+    let configuration = configuration();
+    let database = TestDatabase::empty(&configuration.database).await;
+    let pool = &database.pool;
+    apply_migrations(pool, |version| version < CASE_INSENSITIVE_EMAILS).await;
+    let (oldest, newest, other) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let subscribed_at = Utc::now() - Duration::days(7);
+    let subscribers = [
+        (
+            oldest,
+            "Ursula@Example.com",
+            "pending_confirmation",
+            subscribed_at,
+        ),
+        (newest, "ursula@example.com", "confirmed", Utc::now()),
+        (
+            other,
+            "Le.Guin@example.com",
+            "pending_confirmation",
+            Utc::now(),
+        ),
+    ];
+    for (id, email, status, subscribed_at) in subscribers {
+        sqlx::query(
+            "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+             VALUES ($1, $2, 'Ursula', $3, $4)",
+        )
+        .bind(id)
+        .bind(email)
+        .bind(subscribed_at)
+        .bind(status)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+    for (token, id) in [("oldest-token", oldest), ("newest-token", newest)] {
+        sqlx::query(
+            "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)",
+        )
+        .bind(token)
+        .bind(id)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    // Act
+    apply_migrations(pool, |version| version == CASE_INSENSITIVE_EMAILS).await;
+
+    // Assert
+    let subscribers: Vec<(Uuid, String, String, chrono::DateTime<Utc>)> = sqlx::query_as(
+        "SELECT id, email, status, subscribed_at FROM subscriptions ORDER BY email DESC",
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap();
+    assert_eq!(subscribers.len(), 2);
+    let (id, email, status, merged_subscribed_at) = &subscribers[0];
+    assert_eq!(*id, oldest);
+    // Only the domain is lowercased: local parts are kept as typed.
+    assert_eq!(email, "Ursula@example.com");
+    assert_eq!(status, "confirmed");
+    assert_eq!(merged_subscribed_at.timestamp(), subscribed_at.timestamp());
+    assert_eq!(subscribers[1].0, other);
+    assert_eq!(subscribers[1].1, "Le.Guin@example.com");
+
+    let token_owners: Vec<Uuid> =
+        sqlx::query_scalar("SELECT subscriber_id FROM subscription_tokens")
+            .fetch_all(pool)
+            .await
+            .unwrap();
+    assert_eq!(token_owners, [oldest, oldest]);
+
+    let duplicate = sqlx::query(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+         VALUES ($1, 'URSULA@example.com', 'Ursula', now(), 'confirmed')",
+    )
+    .bind(Uuid::new_v4())
+    .execute(pool)
+    .await;
+    assert!(duplicate.is_err());
+}
@@ -1,6 +1,17 @@
-use crate::helpers::{spawn_app, TestApp};
-use wiremock::matchers::{any, method, path};
-use wiremock::{Mock, ResponseTemplate};
+use wiremock::{
+    matchers::{
+        any,
+        method,
+        path,
+    },
+    Mock,
+    ResponseTemplate,
+};
+
+use crate::helpers::{
+    spawn_app,
+    TestApp,
+};
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Arrange
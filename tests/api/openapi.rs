@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+
+use reqwest::Method;
+use zero2prod::startup::api_routes;
+
+use crate::helpers::spawn_app;
+
+const METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+
+#[tokio::test]
+async fn the_openapi_document_is_served() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let spec: serde_json::Value = response.json().await.unwrap();
+    assert!(spec["openapi"]
+        .as_str()
+        .unwrap()
+        .starts_with('3'));
+}
+
+#[tokio::test]
+async fn the_docs_ui_is_served() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/docs", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("/openapi.json"));
+}
+
+#[tokio::test]
+async fn the_openapi_document_describes_exactly_the_routes_served() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let spec: serde_json::Value = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let documented: BTreeSet<(String, String)> = spec["paths"]
+        .as_object()
+        .expect("The OpenAPI document has no paths.")
+        .iter()
+        .flat_map(|(path, operations)| {
+            operations
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(move |method| (method.to_uppercase(), path.clone()))
+        })
+        .collect();
+    let undocumented: BTreeSet<(String, String)> = api_routes()
+        .into_iter()
+        .filter(|route| !route.documented)
+        .map(|route| (route.method.to_string(), route.path.to_string()))
+        .collect();
+    let paths: BTreeSet<String> = documented
+        .iter()
+        .map(|(_, path)| path.clone())
+        .chain(
+            api_routes()
+                .into_iter()
+                .map(|route| route.path.to_string()),
+        )
+        .collect();
+
+    // Act
+    // What the running server answers, rather than what it is meant to.
+    let mut served = BTreeSet::new();
+    for path in &paths {
+        for method in METHODS {
+            let response = client
+                .request(method.clone(), format!("{}{}", app.address, path))
+                .send()
+                .await
+                .expect("Failed to execute request.");
+            let status = response.status().as_u16();
+            if status != 404 && status != 405 {
+                served.insert((method.to_string(), path.clone()));
+            }
+        }
+    }
+
+    // Assert
+    let served_and_documentable: BTreeSet<_> = served
+        .difference(&undocumented)
+        .cloned()
+        .collect();
+    assert_eq!(documented, served_and_documentable);
+}
+
+#[tokio::test]
+async fn every_route_is_served() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for route in api_routes() {
+        // Act
+        let response = client
+            .request(
+                Method::from_bytes(
+                    route
+                        .method
+                        .as_str()
+                        .as_bytes(),
+                )
+                .unwrap(),
+                format!("{}{}", app.address, route.path),
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        let status = response.status().as_u16();
+        assert!(
+            status != 404 && status != 405,
+            "`{} {}` is listed but not routed (got {}).",
+            route.method,
+            route.path,
+            status
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use wiremock::{
+    matchers::any,
+    Mock,
+    ResponseTemplate,
+};
+use zero2prod::{
+    configuration::{
+        RateLimitingBackend,
+        RouteRateLimits,
+        Settings,
+        TokenBucketSettings,
+    },
+    rate_limiting::delete_stale_buckets,
+    startup::Application,
+};
+
+use crate::helpers::{
+    spawn_app_with,
+    TestApp,
+};
+
+const ONE_PER_HOUR: TokenBucketSettings = TokenBucketSettings {
+    capacity: 1,
+    refill_per_minute: 1.0 / 60.0,
+};
+
+fn limit_subscriptions(c: &mut Settings, limits: RouteRateLimits) {
+    c.rate_limiting.routes = [("/subscriptions".to_string(), limits)].into();
+}
+
+async fn accept_emails(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn subscribing_the_same_email_too_often_returns_429_with_retry_after() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        limit_subscriptions(
+            c,
+            RouteRateLimits {
+                per_email: Some(ONE_PER_HOUR),
+                ..Default::default()
+            },
+        )
+    })
+    .await;
+    accept_emails(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    let first = app
+        .post_subscriptions(body.into())
+        .await;
+    let second = app
+        .post_subscriptions(body.into())
+        .await;
+    let other_email = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    let retry_after: u64 = second.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 3500 && retry_after <= 3600);
+    assert_eq!(other_email.status().as_u16(), 200);
+    let problem: serde_json::Value = second.json().await.unwrap();
+    assert_eq!(problem["code"], "rate_limited");
+    assert_eq!(problem["status"], 429);
+}
+
+#[tokio::test]
+async fn emails_are_compared_case_insensitively_for_rate_limiting() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        limit_subscriptions(
+            c,
+            RouteRateLimits {
+                per_email: Some(ONE_PER_HOUR),
+                ..Default::default()
+            },
+        )
+    })
+    .await;
+    accept_emails(&app).await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40Gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn each_email_domain_has_its_own_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        limit_subscriptions(
+            c,
+            RouteRateLimits {
+                per_email_domain: Some(ONE_PER_HOUR),
+                ..Default::default()
+            },
+        )
+    })
+    .await;
+    accept_emails(&app).await;
+
+    // Act
+    let first = app
+        .post_subscriptions("name=a&email=a%40example.com".into())
+        .await;
+    let same_domain = app
+        .post_subscriptions("name=b&email=b%40example.com".into())
+        .await;
+    let other_domain = app
+        .post_subscriptions("name=c&email=c%40example.org".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(same_domain.status().as_u16(), 429);
+    assert_eq!(other_domain.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn each_client_ip_has_its_own_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        limit_subscriptions(
+            c,
+            RouteRateLimits {
+                per_ip: Some(ONE_PER_HOUR),
+                ..Default::default()
+            },
+        )
+    })
+    .await;
+    accept_emails(&app).await;
+
+    // Act
+    let first = app
+        .post_subscriptions("name=a&email=a%40example.com".into())
+        .await;
+    let second = app
+        .post_subscriptions("name=b&email=b%40example.org".into())
+        .await;
+    // Other routes are not limited.
+    let health_check = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    assert_eq!(health_check.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_postgres_backend_shares_limits_across_instances() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limiting.backend = RateLimitingBackend::Postgres;
+        limit_subscriptions(
+            c,
+            RouteRateLimits {
+                per_email: Some(ONE_PER_HOUR),
+                ..Default::default()
+            },
+        )
+    })
+    .await;
+    accept_emails(&app).await;
+    // A second instance, backed by the same database.
+    let other_instance = Application::build(app.configuration.clone())
+        .await
+        .expect("Failed to build application.");
+    let other_address = format!("http://localhost:{}", other_instance.port());
+    tokio::spawn(other_instance.run_until_stopped());
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    let first = app
+        .post_subscriptions(body.into())
+        .await;
+    let second = reqwest::Client::new()
+        .post(format!("{}/subscriptions", other_address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+async fn rejected_requests_do_not_drain_other_buckets(backend: RateLimitingBackend) {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limiting.backend = backend;
+        limit_subscriptions(
+            c,
+            RouteRateLimits {
+                per_ip: Some(TokenBucketSettings {
+                    capacity: 2,
+                    refill_per_minute: 1.0 / 60.0,
+                }),
+                per_email: Some(ONE_PER_HOUR),
+                ..Default::default()
+            },
+        )
+    })
+    .await;
+    accept_emails(&app).await;
+
+    // Act
+    let first = app
+        .post_subscriptions("name=a&email=a%40example.com".into())
+        .await;
+    let same_email = app
+        .post_subscriptions("name=a&email=a%40example.com".into())
+        .await;
+    // The rejected request gave its per-IP token back.
+    let other_email = app
+        .post_subscriptions("name=b&email=b%40example.com".into())
+        .await;
+    let ip_exhausted = app
+        .post_subscriptions("name=c&email=c%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(same_email.status().as_u16(), 429);
+    assert_eq!(other_email.status().as_u16(), 200);
+    assert_eq!(ip_exhausted.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn rejected_requests_do_not_drain_other_memory_buckets() {
+    rejected_requests_do_not_drain_other_buckets(RateLimitingBackend::Memory).await;
+}
+
+#[tokio::test]
+async fn rejected_requests_do_not_drain_other_postgres_buckets() {
+    rejected_requests_do_not_drain_other_buckets(RateLimitingBackend::Postgres).await;
+}
+
+#[tokio::test]
+async fn stale_postgres_buckets_are_deleted() {
+    // Arrange
+    let app = spawn_app_with(|_| {}).await;
+    sqlx::query(
+        "INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+        VALUES ('stale', 0, now() - interval '2 hours'), ('fresh', 0, now())",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let deleted = delete_stale_buckets(&app.db_pool, std::time::Duration::from_secs(3600))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(deleted, 1);
+    let keys: Vec<String> = sqlx::query_scalar("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys, vec!["fresh".to_string()]);
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{
+        method,
+        path,
+    },
+    Mock,
+    ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn in_flight_requests_complete_before_the_application_stops() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        // Keep the subscription request in flight for a while
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&app.email_server)
+        .await;
+    let in_flight = tokio::spawn(
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send(),
+    );
+    // Wait for the request to reach the email server, i.e. to be in flight.
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Act
+    app.shutdown_handle
+        .shutdown()
+        .await;
+
+    // Assert
+    let response = in_flight
+        .await
+        .unwrap()
+        .expect("The in-flight request was dropped.");
+    assert_eq!(response.status().as_u16(), 200);
+    app.server
+        .await
+        .unwrap()
+        .expect("The application did not stop cleanly.");
+}
+
+#[tokio::test]
+async fn new_connections_are_refused_after_shutdown() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.shutdown_handle
+        .shutdown()
+        .await;
+
+    // Assert
+    let outcome = reqwest::get(format!("{}/health_check", &app.address)).await;
+    assert!(outcome.is_err());
+}
@@ -1,9 +1,17 @@
 use wiremock::{
-    matchers::{method, path},
-    Mock, ResponseTemplate,
+    matchers::{
+        method,
+        path,
+    },
+    Mock,
+    ResponseTemplate,
 };
+use zero2prod::domain::SubscriptionStatus;
 
-use crate::helpers::spawn_app;
+use crate::helpers::{
+    spawn_app,
+    spawn_app_with,
+};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -41,14 +49,90 @@ async fn subscribe_persists_the_new_subscriber() {
         .await;
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus" FROM subscriptions"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_normalized_email() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.subscriber_email
+            .strip_plus_addressing = true
+    })
+    .await;
+    let body = "name=le%20guin&email=Ursula_Le_Guin%2Bnews%40B%C3%BCcher.Example";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into())
+        .await;
+
+    // Assert
+    let email: String = sqlx::query_scalar("SELECT email FROM subscriptions")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
+    assert_eq!(email, "Ursula_Le_Guin@xn--bcher-kva.example");
+}
 
-    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
-    assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "pending_confirmation");
+#[tokio::test]
+async fn subscribe_returns_a_409_for_an_email_only_differing_by_case() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "already_subscribed");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_email_domains_if_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.subscriber_email
+            .reject_disposable_domains = true
+    })
+    .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40mailinator.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["field"], "email");
+    assert_eq!(
+        body["error"],
+        serde_json::json!({"code": "disposable_domain", "domain": "mailinator.com"})
+    );
 }
 
 #[tokio::test]
@@ -119,6 +203,44 @@ async fn subscribe_returns_a_400_when_name_email_is_invalid() {
         );
     }
 }
+
+#[tokio::test]
+async fn subscribe_returns_a_machine_readable_validation_error() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "name=&email=ursula_le_guin%40gmail.com".to_owned(),
+            serde_json::json!({"field": "name", "error": {"code": "empty"}}),
+        ),
+        (
+            format!("name={}&email=ursula_le_guin%40gmail.com", "a".repeat(257)),
+            serde_json::json!({"field": "name", "error": {"code": "too_long", "graphemes": 257, "max": 256}}),
+        ),
+        (
+            "name=U(o&email=ursula_le_guin%40gmail.com".to_owned(),
+            serde_json::json!({"field": "name", "error": {"code": "forbidden_character", "character": "("}}),
+        ),
+        (
+            "name=ursula&email=asdf".to_owned(),
+            serde_json::json!({"field": "email", "error": {"code": "missing_at_symbol", "email": "asdf"}}),
+        ),
+    ];
+    for (body, expected) in test_cases {
+        // Act
+        let response = app
+            .post_subscriptions(body)
+            .await;
+
+        // Assert
+        assert_eq!(400, response.status().as_u16());
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "validation_failed");
+        assert!(body["detail"].is_string());
+        assert_eq!(body["field"], expected["field"]);
+        assert_eq!(body["error"], expected["error"]);
+    }
+}
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // Arrange
@@ -213,3 +335,42 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_propagates_the_incoming_trace_context_to_the_email_provider() {
+    // Arrange
+    let app = spawn_app().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()[0];
+    let traceparent = email_request
+        .headers
+        .get("traceparent")
+        .expect("The email request carries no trace context.")
+        .to_str()
+        .unwrap();
+    assert_eq!(traceparent.split('-').nth(1), Some(trace_id));
+}
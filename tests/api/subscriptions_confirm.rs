@@ -8,8 +8,13 @@ use wiremock::{
     Mock,
     ResponseTemplate,
 };
+use zero2prod::domain::SubscriptionStatus;
 
-use crate::helpers::spawn_app;
+use crate::helpers::{
+    spawn_app,
+    spawn_app_with,
+    TestApp,
+};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -55,12 +60,212 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus" FROM subscriptions"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_cannot_be_confirmed_with_an_old_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let status: SubscriptionStatus = sqlx::query_scalar("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, SubscriptionStatus::Unsubscribed);
+}
+
+#[tokio::test]
+async fn following_a_link_shows_the_configured_confirmation_page() {
+    // Arrange
+    let app = TestApp::builder()
+        .configure(|c| c.confirmation_page.brand_name = "Earthsea <Gazette>".into())
+        .accept_emails()
+        .spawn()
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let subscription_token: String =
+        sqlx::query_scalar("SELECT subscription_token FROM subscription_tokens")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let link = app.confirmation_link(&subscription_token);
+
+    // Act
+    let first = reqwest::get(link.clone())
+        .await
+        .unwrap();
+    let first_status = first.status();
+    let first_body = first.text().await.unwrap();
+    let second = reqwest::get(link)
+        .await
+        .unwrap();
+    let second_status = second.status();
+    let second_body = second.text().await.unwrap();
+
+    // Assert
+    assert_eq!(first_status.as_u16(), 200);
+    assert!(first_body.contains("Earthsea &lt;Gazette&gt;"));
+    assert!(first_body.contains(
+        &app.configuration
+            .confirmation_page
+            .confirmed
+            .title
+    ));
+    assert_eq!(second_status.as_u16(), 200);
+    assert!(second_body.contains(
+        &app.configuration
+            .confirmation_page
+            .already_confirmed
+            .title
+    ));
+}
+
+#[tokio::test]
+async fn api_clients_get_problem_details_for_unknown_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(app.confirmation_link("unknown"))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_token");
+    assert_eq!(body["status"], 401);
+}
+
+#[tokio::test]
+async fn expired_links_are_rejected_with_a_410() {
+    // Arrange
+    let app = TestApp::builder()
+        .accept_emails()
+        .spawn()
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let validity_hours = app
+        .configuration
+        .application
+        .confirmation_link_validity_hours;
+    let subscription_token: String = sqlx::query_scalar(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(hours => $1::int) \
+         RETURNING subscription_token",
+    )
+    .bind(validity_hours as i32 + 1)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let link = app.confirmation_link(&subscription_token);
+
+    // Act
+    let api_response = reqwest::Client::new()
+        .get(link.clone())
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+    let page = reqwest::get(link)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(api_response.status().as_u16(), 410);
+    let body: serde_json::Value = api_response
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["code"], "expired_token");
+    assert_eq!(page.status().as_u16(), 410);
+    assert!(page
+        .text()
+        .await
+        .unwrap()
+        .contains(
+            &app.configuration
+                .confirmation_page
+                .expired_token
+                .title
+        ));
+    let status: SubscriptionStatus = sqlx::query_scalar("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn browsers_are_redirected_when_a_redirect_url_is_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.confirmation_page
+            .redirect_url = Some("https://example.com/subscribed".into())
+    })
+    .await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let response = client
+        .get(app.confirmation_link("unknown"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/subscribed?status=invalid_token"
+    );
 }
@@ -0,0 +1,106 @@
+//! tests/api/testkit.rs
+use zero2prod::{
+    domain::SubscriptionStatus,
+    testkit::TestApp,
+};
+
+#[tokio::test]
+async fn preloaded_subscribers_are_stored_in_the_requested_status() {
+    // Arrange
+    let app = TestApp::builder()
+        .subscriber("ursula@example.com", SubscriptionStatus::Confirmed)
+        .subscriber("le.guin@example.com", SubscriptionStatus::Bounced)
+        .spawn()
+        .await;
+
+    // Act
+    let statuses: Vec<(String, SubscriptionStatus)> =
+        sqlx::query_as("SELECT email, status FROM subscriptions ORDER BY email DESC")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+
+    // Assert
+    assert_eq!(
+        statuses,
+        [
+            ("ursula@example.com".into(), SubscriptionStatus::Confirmed),
+            ("le.guin@example.com".into(), SubscriptionStatus::Bounced),
+        ]
+    );
+    assert_eq!(app.subscribers[1].status, SubscriptionStatus::Bounced);
+}
+
+#[tokio::test]
+async fn preloaded_pending_subscribers_can_follow_their_confirmation_link() {
+    // Arrange
+    let app = TestApp::builder()
+        .subscriber(
+            "ursula@example.com",
+            SubscriptionStatus::PendingConfirmation,
+        )
+        .spawn()
+        .await;
+
+    // Act
+    let response = reqwest::get(app.confirmation_link(&app.subscribers[0].subscription_token))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_inbox_captures_the_emails_sent_and_their_links() {
+    // Arrange
+    let app = TestApp::builder()
+        .accept_emails()
+        .spawn()
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let email = app
+        .inbox()
+        .last_email_to("ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(email.subject, "Welcome!");
+    let confirmation_links = app.confirmation_links(&email);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_admin_client_belongs_to_a_stored_administrator() {
+    // Arrange
+    let app = TestApp::builder()
+        .spawn()
+        .await;
+
+    // Act
+    let admin = app.admin_client().await;
+
+    // Assert
+    let username: String = sqlx::query_scalar("SELECT username FROM users WHERE user_id = $1")
+        .bind(admin.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(username, admin.username);
+    let response = admin
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Body", "html": "<p>Body</p>" }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}